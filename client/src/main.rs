@@ -2,6 +2,7 @@ use anyhow::{anyhow, Result};
 use futures_util::stream::StreamExt;
 use futures_util::FutureExt;
 use sdc_core::types::{ProfileButtonPressed, SetButtonUI, WsActions};
+use std::collections::HashMap;
 use std::env;
 use std::process::exit;
 use std::str::FromStr;
@@ -20,16 +21,25 @@ mod stream_deck_device;
 const STREAM_DECK_API_URL_VAR: &str = "STREAM_DECK_API_URL";
 const STREAM_DECK_BRIGHTNESS_VAR: &str = "STREAM_DECK_BRIGHTNESS";
 const STREAM_DECK_SLEEP_TIMEOUT_MIN_VAR: &str = "STREAM_DECK_SLEEP_TIMEOUT_MIN";
+const STREAM_DECK_CLIENT_NAME_VAR: &str = "STREAM_DECK_CLIENT_NAME";
 
 const STREAMDECK_DEFAULT_BRIGHTNESS: u8 = 50;
 const SCREEN_SLEEP_MIN: u64 = 5;
 const MIN_TO_SEC: u64 = 60;
+// how much to darken a key while it is held down
+const PRESSED_DARKEN: u8 = 80;
 
 struct SetButtonRequest {
     state: SetButtonUI,
     button: u8,
+    pressed: bool,
+    // transient states are drawn but not kept, so releasing the button redraws its previous state
+    transient: bool,
 }
 
+// last state set on each button, used to redraw buttons while they are pressed
+type ButtonStates = Arc<Mutex<HashMap<u8, SetButtonUI>>>;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -57,6 +67,7 @@ async fn main() {
         .expect("failed to set streamdeck brightness, check streamdeck connection");
 
     let (image_update_tx, image_update_rx) = mpsc::unbounded_channel::<SetButtonRequest>();
+    let button_states = ButtonStates::default();
     let (ws_write, ws_read) = ws_stream.split();
     let (client_sender, client_rcv) = mpsc::unbounded_channel();
 
@@ -75,14 +86,32 @@ async fn main() {
             }),
     );
 
+    // let the server know the key layout so it can render backgrounds across the whole deck
+    // and its name, which the server config can use to target this deck
+    let client_info = WsActions::ClientInfo {
+        layout: device.layout(),
+        name: env::var(STREAM_DECK_CLIENT_NAME_VAR).ok(),
+    };
+    match serde_json::to_string(&client_info) {
+        Ok(msg) => client_sender
+            .send(Message::text(msg))
+            .unwrap_or_else(|err| error!(error=?err, "failed to send client info")),
+        Err(err) => error!(error=?err, "failed to convert client info to string"),
+    };
+
     let handle_button_requests_join = tokio::spawn(handle_set_button_requests(
         image_update_rx,
         deck_ref.clone(),
         device,
+        button_states.clone(),
     ));
 
-    let stream_deck_listener_join =
-        tokio::spawn(start_stream_deck_listener(deck_ref, client_sender.clone()));
+    let stream_deck_listener_join = tokio::spawn(start_stream_deck_listener(
+        deck_ref,
+        client_sender.clone(),
+        image_update_tx.clone(),
+        button_states,
+    ));
     ws_read
         .for_each(|message| async {
             // tokio_tungstenite responds to ping with pong already, no need to worry about it
@@ -144,10 +173,17 @@ async fn handle_set_button_requests(
     mut rx: mpsc::UnboundedReceiver<SetButtonRequest>,
     deck_ref: Arc<Mutex<StreamDeck>>,
     device: StreamDeckDevice,
+    button_states: ButtonStates,
 ) {
     while let Some(set_button_request) = rx.recv().await {
         info!(button = set_button_request.button, "setting button image");
         match set_button_state(&set_button_request, &deck_ref, &device).await {
+            Ok(_) if !set_button_request.pressed && !set_button_request.transient => {
+                button_states
+                    .lock()
+                    .await
+                    .insert(set_button_request.button, set_button_request.state);
+            }
             Ok(_) => (),
             Err(e) => {
                 info!(
@@ -174,11 +210,14 @@ async fn set_button_state(
         let image = image::load_from_memory(&img_str)
             .map_err(|e| anyhow!("failed to load image from into memory: {}", e))?;
 
-        let resized_image = image.resize(
+        let mut resized_image = image.resize(
             image_width,
             image_height,
             image::imageops::FilterType::Nearest,
         );
+        if set_button_request.pressed {
+            resized_image = resized_image.brighten(-i32::from(PRESSED_DARKEN));
+        }
 
         deck_ref
             .lock()
@@ -194,8 +233,13 @@ async fn set_button_state(
     }
 
     if let Some(color_str) = &set_button_request.state.color {
-        let color = Colour::from_str(color_str)
+        let mut color = Colour::from_str(color_str)
             .map_err(|e| anyhow!("invalid color {}: {}", color_str, e))?;
+        if set_button_request.pressed {
+            color.r = color.r.saturating_sub(PRESSED_DARKEN);
+            color.g = color.g.saturating_sub(PRESSED_DARKEN);
+            color.b = color.b.saturating_sub(PRESSED_DARKEN);
+        }
 
         deck_ref
             .lock()
@@ -248,11 +292,34 @@ async fn handle_socket_message(
             .send(SetButtonRequest {
                 state: button,
                 button: index,
+                pressed: false,
+                transient: false,
+            })
+            .map_err(|e| anyhow!("{}", e)),
+        WsActions::FlashButton { index, button } => image_update_tx
+            .send(SetButtonRequest {
+                state: button,
+                button: index,
+                pressed: false,
+                transient: true,
             })
             .map_err(|e| anyhow!("{}", e)),
         WsActions::SetButtons { buttons } => {
             send_button_update_requests(image_update_tx, buttons, device).await
         }
+        // the server follows up with a flash on the key, so only log the details here
+        WsActions::ActionResult {
+            button,
+            success,
+            message,
+        } => {
+            if success {
+                info!(button, message, "button actions completed");
+            } else {
+                error!(button, message, "button actions failed");
+            }
+            Ok(())
+        }
         _ => Err(anyhow!("unknown message")),
     };
     match r {
@@ -271,6 +338,8 @@ async fn send_button_update_requests(
             .send(SetButtonRequest {
                 state: button.clone(),
                 button: i as u8,
+                pressed: false,
+                transient: false,
             })
             .map_err(|e| anyhow!("{}", e))?
     }
@@ -286,9 +355,11 @@ async fn send_button_update_requests(
             .send(SetButtonRequest {
                 state: SetButtonUI {
                     color: Some("000000".to_string()),
-                    image: None,
+                    ..Default::default()
                 },
                 button: i as u8,
+                pressed: false,
+                transient: false,
             })
             .map_err(|e| anyhow!("{}", e))?
     }
@@ -299,7 +370,10 @@ async fn send_button_update_requests(
 async fn start_stream_deck_listener(
     deck_ref: Arc<Mutex<StreamDeck>>,
     write: mpsc::UnboundedSender<Message>,
+    image_update_tx: mpsc::UnboundedSender<SetButtonRequest>,
+    button_states: ButtonStates,
 ) {
+    let mut pressed_state: Vec<u8> = Vec::new();
     let mut last_button_press_time = std::time::SystemTime::now();
     let mut is_asleep = false;
     let sleep_timeout = env::var(STREAM_DECK_SLEEP_TIMEOUT_MIN_VAR)
@@ -313,8 +387,18 @@ async fn start_stream_deck_listener(
         let button_state_option = read_stream_deck(&deck_ref).await;
 
         if let Some(button_state) = button_state_option {
+            let previous_state = std::mem::replace(&mut pressed_state, button_state.clone());
             for (i, state) in button_state.iter().enumerate() {
+                let was_pressed = previous_state.get(i).is_some_and(|s| !s.eq(&0));
                 if state.eq(&0) {
+                    // redraw the button normally once it is released
+                    if was_pressed {
+                        send_pressed_request(&image_update_tx, &button_states, i, false).await;
+                    }
+                    continue;
+                }
+                // only a new press counts, not a button that is still held
+                if was_pressed {
                     continue;
                 }
 
@@ -328,6 +412,9 @@ async fn start_stream_deck_listener(
                     break;
                 }
 
+                // give instant feedback, the server flashes the result once the actions finish
+                send_pressed_request(&image_update_tx, &button_states, i, true).await;
+
                 let map = ProfileButtonPressed {
                     profile: None,
                     button: i,
@@ -361,6 +448,32 @@ async fn start_stream_deck_listener(
     }
 }
 
+async fn send_pressed_request(
+    image_update_tx: &mpsc::UnboundedSender<SetButtonRequest>,
+    button_states: &ButtonStates,
+    button: usize,
+    pressed: bool,
+) {
+    let button: u8 = match button.try_into() {
+        Ok(button) => button,
+        Err(_) => return,
+    };
+    let state = match button_states.lock().await.get(&button) {
+        Some(state) => state.clone(),
+        None => return,
+    };
+
+    let request = SetButtonRequest {
+        state,
+        button,
+        pressed,
+        transient: false,
+    };
+    if let Err(err) = image_update_tx.send(request) {
+        error!(button, "failed to send pressed button update: {}", err);
+    }
+}
+
 async fn read_stream_deck(deck_ref: &Arc<Mutex<StreamDeck>>) -> Option<Vec<u8>> {
     let states = deck_ref.lock().await.read_buttons(None);
     match states {
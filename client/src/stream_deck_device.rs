@@ -1,5 +1,9 @@
+use sdc_core::types::DeckLayout;
+use std::env;
 use std::fmt;
 
+const STREAM_DECK_KEY_GAP_VAR: &str = "STREAM_DECK_KEY_GAP";
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct StreamDeckDevice {
     internal_type: StreamDeckDeviceTypes,
@@ -59,6 +63,45 @@ impl StreamDeckDevice {
     pub fn pid(self) -> u16 {
         return self.pid;
     }
+
+    pub fn columns(self) -> u8 {
+        match self.internal_type {
+            StreamDeckDeviceTypes::Mini => 3,
+            StreamDeckDeviceTypes::Original
+            | StreamDeckDeviceTypes::OriginalV2
+            | StreamDeckDeviceTypes::Mk2 => 5,
+            StreamDeckDeviceTypes::Xl => 8,
+        }
+    }
+
+    // gap between keys in pixels of the key image. Elgato doesn't publish these, so the defaults are
+    // estimates from the key pitch of each model, set STREAM_DECK_KEY_GAP to override them.
+    pub fn key_gap(self) -> u32 {
+        if let Some(key_gap) = env::var(STREAM_DECK_KEY_GAP_VAR)
+            .ok()
+            .and_then(|key_gap| key_gap.parse().ok())
+        {
+            return key_gap;
+        }
+
+        match self.internal_type {
+            StreamDeckDeviceTypes::Original
+            | StreamDeckDeviceTypes::OriginalV2
+            | StreamDeckDeviceTypes::Mk2 => 20,
+            StreamDeckDeviceTypes::Mini => 24,
+            StreamDeckDeviceTypes::Xl => 22,
+        }
+    }
+
+    pub fn layout(self) -> DeckLayout {
+        let (key_size, _) = self.image_size();
+        DeckLayout {
+            rows: self.keys() / self.columns(),
+            columns: self.columns(),
+            key_size: key_size as u32,
+            key_gap: self.key_gap(),
+        }
+    }
 }
 
 impl fmt::Display for StreamDeckDevice {
@@ -1,5 +1,9 @@
 use serde::{Deserialize, Serialize};
 
+// color temperature range accepted by the bridge, lights report their own range in MirekSchema
+pub const MIREK_MINIMUM: u32 = 153;
+pub const MIREK_MAXIMUM: u32 = 500;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Component {
     pub x: f32,
@@ -44,6 +48,36 @@ impl RGB8 {
     pub fn new(r: u8, g: u8, b: u8) -> RGB8 {
         RGB8 { r, g, b }
     }
+
+    // from_hex parses #rrggbb, the # is optional
+    pub fn from_hex(hex: &str) -> Option<RGB8> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(RGB8::new(component(0)?, component(2)?, component(4)?))
+    }
+}
+
+impl MirekSchema {
+    pub fn clamp(&self, mirek: u32) -> u32 {
+        mirek.clamp(self.mirek_minimum, self.mirek_maximum)
+    }
+}
+
+impl Default for MirekSchema {
+    fn default() -> MirekSchema {
+        MirekSchema {
+            mirek_maximum: MIREK_MAXIMUM,
+            mirek_minimum: MIREK_MINIMUM,
+        }
+    }
+}
+
+// mirek is 1,000,000 divided by the color temperature in kelvin
+pub fn kelvin_to_mirek(kelvin: u32) -> u32 {
+    1_000_000 / kelvin.max(1)
 }
 
 impl Component {
@@ -69,6 +103,16 @@ impl Gamut {
         Gamut { red, green, blue }
     }
 
+    // gamut_c is the gamut of current color lights, used when a resource doesn't report one such as
+    // a grouped light
+    pub fn gamut_c() -> Gamut {
+        Gamut::new(
+            Component::unchecked(0.6915f32, 0.3083f32),
+            Component::unchecked(0.17f32, 0.7f32),
+            Component::unchecked(0.1532f32, 0.0475f32),
+        )
+    }
+
     pub fn contains(&self, xy: &Component) -> bool {
         let s = (self.red.x - self.blue.x) * (xy.y - self.blue.y)
             - (self.red.y - self.blue.y) * (xy.x - self.blue.x);
@@ -316,6 +360,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rgb_from_hex() {
+        assert_rgb_eq!(
+            RGB8::new(255, 136, 0),
+            RGB8::from_hex("#ff8800").unwrap(),
+            0
+        );
+        assert_rgb_eq!(RGB8::new(18, 52, 86), RGB8::from_hex("123456").unwrap(), 0);
+        assert!(RGB8::from_hex("#fff").is_none());
+        assert!(RGB8::from_hex("#gg0000").is_none());
+    }
+
+    #[test]
+    fn mirek_from_kelvin() {
+        assert_eq!(kelvin_to_mirek(2000), 500);
+        assert_eq!(kelvin_to_mirek(6500), 153);
+        assert_eq!(
+            MirekSchema::default().clamp(kelvin_to_mirek(10000)),
+            MIREK_MINIMUM
+        );
+    }
+
     #[test]
     fn gamut_xy_to_rgb_inside() {
         let gamut = Gamut::new(
@@ -0,0 +1,56 @@
+use crate::models::entertainment::GetEntertainmentConfigurationsResponseItem;
+
+pub type EntertainmentConfigurations = Vec<EntertainmentConfiguration>;
+
+// EntertainmentConfiguration is an entertainment area, unlike rooms and zones it has no grouped
+// light so changes have to be sent to each of its lights
+pub struct EntertainmentConfiguration {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub light_ids: Vec<uuid::Uuid>,
+}
+
+impl EntertainmentConfiguration {
+    pub fn new(
+        configuration: GetEntertainmentConfigurationsResponseItem,
+    ) -> EntertainmentConfiguration {
+        EntertainmentConfiguration {
+            id: configuration.id,
+            name: configuration.metadata.name,
+            light_ids: configuration
+                .light_services
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|service| service.rtype == "light")
+                .map(|service| service.rid)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::entertainment::GetEntertainmentConfigurationsResponse;
+
+    #[test]
+    fn entertainment_configuration_lights() {
+        let response: GetEntertainmentConfigurationsResponse = serde_json::from_str(
+            r#"{"errors":[],"data":[{"id":"d1b0e6f4-3a2c-4e5b-9f8a-7c6d5e4f3a2b","type":"entertainment_configuration","metadata":{"name":"TV area"},"configuration_type":"screen","status":"inactive","light_services":[{"rid":"4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e","rtype":"light"},{"rid":"9a1c2b3d-4e5f-4a6b-8c7d-0e1f2a3b4c5d","rtype":"light"}]}]}"#,
+        )
+        .unwrap();
+        let area = EntertainmentConfiguration::new(response.data.unwrap().remove(0));
+
+        assert_eq!(area.name, "TV area");
+        assert_eq!(
+            area.light_ids
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e",
+                "9a1c2b3d-4e5f-4a6b-8c7d-0e1f2a3b4c5d"
+            ]
+        );
+    }
+}
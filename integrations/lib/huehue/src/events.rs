@@ -0,0 +1,238 @@
+use std::collections::VecDeque;
+
+use futures_util::stream::{self, Stream};
+use reqwest::Response;
+
+use crate::color::Component;
+use crate::http::HueError;
+use crate::light::LightResource;
+use crate::models::events::{EventContainer, EventResource, LightEventFields, SceneEventFields};
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    // a light or grouped light changed
+    Light(LightEvent),
+    // a scene was recalled or stopped being active
+    Scene(SceneEvent),
+}
+
+// LightEvent only has the fields that changed set
+#[derive(Debug, Clone)]
+pub struct LightEvent {
+    pub id: uuid::Uuid,
+    pub resource: LightResource,
+    pub on: Option<bool>,
+    pub brightness: Option<f32>,
+    pub xy: Option<Component>,
+    // Some(None) when the light switched to color mode and has no color temperature anymore
+    pub mirek: Option<Option<u32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneEvent {
+    pub id: uuid::Uuid,
+    pub active: bool,
+}
+
+// EventBuffer collects chunks of the event stream until a full message, which ends with an empty
+// line, has been received
+#[derive(Debug, Default)]
+struct EventBuffer {
+    buffer: Vec<u8>,
+}
+
+impl EventBuffer {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend(chunk.iter().filter(|b| **b != b'\r'));
+
+        let mut messages = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let message: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            messages.push(String::from_utf8_lossy(&message[..end]).into_owned());
+        }
+        messages
+    }
+}
+
+// parse_message turns one event stream message into events, resource types other than lights and
+// scenes and resources that can't be parsed are skipped
+fn parse_message(message: &str) -> Result<Vec<Event>, serde_json::Error> {
+    let data: Vec<&str> = message
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim)
+        .collect();
+    if data.is_empty() {
+        // comments such as ": hi" and ids without data
+        return Ok(Vec::new());
+    }
+
+    let containers: Vec<EventContainer> = serde_json::from_str(&data.join("\n"))?;
+    Ok(containers
+        .into_iter()
+        .filter(|container| container.r#type == "update")
+        .flat_map(|container| container.data)
+        .filter_map(|resource| parse_resource(resource).ok().flatten())
+        .collect())
+}
+
+// parse_resource returns the event for one changed resource, if it is one that is tracked
+fn parse_resource(resource: serde_json::Value) -> Result<Option<Event>, serde_json::Error> {
+    let resource: EventResource = serde_json::from_value(resource)?;
+    let fields = serde_json::Value::Object(resource.fields);
+    match resource.r#type.as_str() {
+        "light" | "grouped_light" => {
+            let fields: LightEventFields = serde_json::from_value(fields)?;
+            Ok(Some(Event::Light(LightEvent {
+                id: resource.id,
+                resource: LightResource::from_type(&resource.r#type),
+                on: fields.on.map(|on| on.on),
+                brightness: fields.dimming.map(|dimming| dimming.brightness),
+                xy: fields.color.map(|color| color.xy),
+                mirek: fields
+                    .color_temperature
+                    .map(|temperature| temperature.mirek),
+            })))
+        }
+        "scene" => {
+            let fields: SceneEventFields = serde_json::from_value(fields)?;
+            Ok(fields.status.map(|status| {
+                Event::Scene(SceneEvent {
+                    id: resource.id,
+                    active: status.active != "inactive",
+                })
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+struct EventStreamState {
+    // none once the connection failed
+    response: Option<Response>,
+    buffer: EventBuffer,
+    pending: VecDeque<Result<Event, HueError>>,
+}
+
+impl EventStreamState {
+    // push_chunk queues the events of every message the chunk completes. A message that can't be
+    // parsed queues an error in its place, the messages after it are still queued.
+    fn push_chunk(&mut self, chunk: &[u8]) {
+        for message in self.buffer.push(chunk) {
+            match parse_message(&message) {
+                Ok(events) => self.pending.extend(events.into_iter().map(Ok)),
+                Err(_) => self.pending.push_back(Err(HueError::Unexpected)),
+            }
+        }
+    }
+}
+
+// event_stream yields the events of an event stream response. Messages that can't be parsed are
+// returned as errors without ending the stream, the stream ends when the connection does.
+pub(crate) fn event_stream(response: Response) -> impl Stream<Item = Result<Event, HueError>> {
+    let state = EventStreamState {
+        response: Some(response),
+        buffer: EventBuffer::default(),
+        pending: VecDeque::new(),
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((event, state));
+            }
+
+            let chunk = match state.response.as_mut()?.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => return None,
+                Err(e) => {
+                    state.response = None;
+                    return Some((Err(HueError::from(e)), state));
+                }
+            };
+
+            state.push_chunk(&chunk);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHT_MESSAGE: &str = r#"id: 1681056384:0
+data: [{"creationtime":"2023-04-09T16:06:24Z","data":[{"id":"4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e","id_v1":"/lights/1","on":{"on":true},"owner":{"rid":"7e0c4b5a-1d5e-4a5e-9c8b-2a0f6d5e3c1b","rtype":"device"},"type":"light"},{"id":"4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e","dimming":{"brightness":42.5},"type":"light"},{"id":"9a1c2b3d-4e5f-4a6b-8c7d-0e1f2a3b4c5d","status":"connected","type":"zigbee_connectivity"}],"id":"d1b0e6f4-3a2c-4e5b-9f8a-7c6d5e4f3a2b","type":"update"}]"#;
+
+    #[test]
+    fn parse_light_update() {
+        let events = parse_message(LIGHT_MESSAGE).unwrap();
+        assert_eq!(events.len(), 2);
+
+        match &events[0] {
+            Event::Light(event) => {
+                assert_eq!(event.on, Some(true));
+                assert!(event.brightness.is_none());
+                assert!(matches!(event.resource, LightResource::Light));
+            }
+            _ => panic!("expected a light event"),
+        }
+        match &events[1] {
+            Event::Light(event) => assert_eq!(event.brightness, Some(42.5)),
+            _ => panic!("expected a light event"),
+        }
+    }
+
+    #[test]
+    fn parse_scene_recall() {
+        let message = r#"data: [{"data":[{"id":"0b4d3c2a-1f0e-4d9c-8b7a-6f5e4d3c2b1a","status":{"active":"static"},"type":"scene"}],"type":"update"}]"#;
+        match parse_message(message).unwrap().as_slice() {
+            [Event::Scene(event)] => assert!(event.active),
+            events => panic!("expected a scene event, got {:?}", events),
+        }
+    }
+
+    #[test]
+    fn buffer_split_messages() {
+        let mut buffer = EventBuffer::default();
+        assert!(buffer.push(b": hi\n\nid: 1\r\ndata: [").len() == 1);
+        let messages = buffer.push(b"]\r\n\r\n");
+        assert_eq!(messages, vec!["id: 1\ndata: []".to_string()]);
+        assert!(parse_message(&messages[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn skip_bad_resource() {
+        let message = r#"data: [{"data":[{"id":"not a uuid","type":"light"},{"id":"4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e","dimming":{"brightness":"bright"},"type":"light"},{"id":"4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e","on":{"on":false},"type":"light"}],"type":"update"}]"#;
+        match parse_message(message).unwrap().as_slice() {
+            [Event::Light(event)] => assert_eq!(event.on, Some(false)),
+            events => panic!("expected one light event, got {:?}", events),
+        }
+    }
+
+    #[test]
+    fn bad_message_keeps_rest_of_chunk() {
+        let mut state = EventStreamState {
+            response: None,
+            buffer: EventBuffer::default(),
+            pending: VecDeque::new(),
+        };
+        state.push_chunk(format!("data: [{{\n\n{}\n\n", LIGHT_MESSAGE).as_bytes());
+
+        assert_eq!(state.pending.len(), 3);
+        assert!(matches!(state.pending[0], Err(HueError::Unexpected)));
+        assert!(matches!(state.pending[1], Ok(Event::Light(_))));
+        assert!(matches!(state.pending[2], Ok(Event::Light(_))));
+    }
+
+    #[test]
+    fn parse_color_mode_clears_mirek() {
+        let message = r#"data: [{"data":[{"id":"4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e","color":{"xy":{"x":0.4,"y":0.5}},"color_temperature":{"mirek":null,"mirek_valid":false},"type":"light"},{"id":"4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e","dimming":{"brightness":10.0},"type":"light"}],"type":"update"}]"#;
+        match parse_message(message).unwrap().as_slice() {
+            [Event::Light(color), Event::Light(dimming)] => {
+                assert_eq!(color.mirek, Some(None));
+                assert_eq!(dimming.mirek, None);
+            }
+            events => panic!("expected two light events, got {:?}", events),
+        }
+    }
+}
@@ -1,20 +1,24 @@
-use reqwest::header::HeaderMap;
-use reqwest::{Certificate, Client, ClientBuilder, Error, StatusCode};
+use reqwest::{Client, Error, RequestBuilder, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::fmt::Display;
+use std::time::Duration;
 use url::Url;
 
-use crate::certificate::CERTIFICATE;
 use crate::models::error::ErrorCode;
+use crate::{tls, Hue};
 
 const HUE_APPLICATION_KEY_HEADER: &str = "hue-application-key";
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 pub enum HueError {
     Unauthorized,
     AlreadyAuthorized,
     Connection,
+    // the http client couldn't be built, eg because of a mismatched tls config
+    Client(reqwest::Error),
+    Certificate(String),
     Response(reqwest::Error),
     Unsupported,
     Unexpected,
@@ -26,6 +30,14 @@ impl Display for HueError {
             HueError::Unauthorized => write!(f, "hue unauthorized"),
             HueError::AlreadyAuthorized => write!(f, "hue is already authorized"),
             HueError::Connection => write!(f, "hue connection error"),
+            HueError::Client(err) => write!(f, "hue client couldn't be created: {}", err),
+            HueError::Certificate(reason) => {
+                write!(
+                    f,
+                    "hue bridge presented an unexpected certificate: {}",
+                    reason
+                )
+            }
             HueError::Response(response_err) => write!(f, "hue response error: {}", response_err),
             HueError::Unsupported => write!(f, "hue unsupported call"),
             HueError::Unexpected => write!(f, "hue unexpected call"),
@@ -35,32 +47,27 @@ impl Display for HueError {
 }
 impl std::error::Error for HueError {}
 
-fn build_base() -> ClientBuilder {
+// build creates a client for a bridge, any bridge signed by the hue root when bridge_id isn't known
+// yet. Clients keep connections alive, so reuse them rather than building one per request.
+pub fn build(bridge_id: Option<&str>) -> Result<Client, HueError> {
     reqwest::Client::builder()
-        .add_root_certificate(Certificate::from_pem(CERTIFICATE.as_bytes()).unwrap())
-        .danger_accept_invalid_certs(true)
-    // todo: rusttls doesn't support this so allow any cert, just internal so not the worst
-    // .danger_accept_invalid_hostnames(true)
-}
-
-pub fn build() -> Client {
-    build_base().build().unwrap()
+        .use_preconfigured_tls(tls::client_config(bridge_id))
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .build()
+        .map_err(HueError::Client)
 }
 
-pub fn build_with_key(application_key: String) -> Client {
-    let mut headers = HeaderMap::new();
-    headers.insert(HUE_APPLICATION_KEY_HEADER, application_key.parse().unwrap());
-
-    build_base().default_headers(headers).build().unwrap()
+// with_key adds the application key to a request made with the hue's client
+pub fn with_key(hue: &Hue, request: RequestBuilder) -> Result<RequestBuilder, HueError> {
+    let application_key = hue.application_key().ok_or(HueError::Unauthorized)?;
+    Ok(request.header(HUE_APPLICATION_KEY_HEADER, application_key))
 }
 
-pub async fn get_auth<R>(application_key: String, url: Url) -> Result<R, HueError>
+pub async fn get_auth<R>(hue: &Hue, url: Url) -> Result<R, HueError>
 where
     R: DeserializeOwned,
 {
-    let client = build_with_key(application_key);
-
-    let response = match client.get(url).send().await {
+    let response = match with_key(hue, hue.client().get(url))?.send().await {
         Ok(response) => response,
         Err(e) => return Err(HueError::from(e)),
     };
@@ -72,10 +79,8 @@ where
 }
 
 #[allow(unused)]
-pub async fn get_auth_text(application_key: String, url: Url) -> Result<String, HueError> {
-    let client = build_with_key(application_key);
-
-    let response = match client.get(url).send().await {
+pub async fn get_auth_text(hue: &Hue, url: Url) -> Result<String, HueError> {
+    let response = match with_key(hue, hue.client().get(url))?.send().await {
         Ok(response) => response,
         Err(e) => return Err(HueError::from(e)),
     };
@@ -86,14 +91,16 @@ pub async fn get_auth_text(application_key: String, url: Url) -> Result<String,
     }
 }
 
-pub async fn put_auth<R, T>(application_key: String, url: Url, object: &T) -> Result<R, HueError>
+pub async fn put_auth<R, T>(hue: &Hue, url: Url, object: &T) -> Result<R, HueError>
 where
     T: Serialize,
     R: DeserializeOwned,
 {
-    let client = build_with_key(application_key);
-
-    let response = match client.put(url).json(&object).send().await {
+    let response = match with_key(hue, hue.client().put(url))?
+        .json(&object)
+        .send()
+        .await
+    {
         Ok(response) => response,
         Err(e) => return Err(HueError::from(e)),
     };
@@ -104,9 +111,29 @@ where
     }
 }
 
+// certificate_error finds the reason the tls handshake failed, hyper wraps it in an io error
+fn certificate_error(e: &Error) -> Option<String> {
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        let tls_err = match err.downcast_ref::<std::io::Error>() {
+            Some(io_err) => io_err
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>()),
+            None => err.downcast_ref::<rustls::Error>(),
+        };
+        if let Some(tls_err) = tls_err {
+            return Some(tls_err.to_string());
+        }
+        source = err.source();
+    }
+    None
+}
+
 impl From<Error> for HueError {
     fn from(e: Error) -> Self {
-        if e.is_status() {
+        if let Some(reason) = certificate_error(&e) {
+            HueError::Certificate(reason)
+        } else if e.is_status() {
             match e.status().unwrap() {
                 StatusCode::UNAUTHORIZED => HueError::Unauthorized,
                 _ => HueError::Unknown,
@@ -1,41 +1,47 @@
 use std::net::Ipv4Addr;
 use std::time::Duration;
 
+use futures_util::stream::Stream;
 use url::Url;
 
 use crate::device::{Device, Devices};
+use crate::entertainment::{EntertainmentConfiguration, EntertainmentConfigurations};
+use crate::events::{self, Event};
 use crate::http::HueError;
 use crate::light::Lights;
 use crate::models::create_user::{CreateUserRequest, CreateUserResponse};
 use crate::models::device_type::DeviceType;
 use crate::models::devices::GetDevicesResponse;
+use crate::models::entertainment::GetEntertainmentConfigurationsResponse;
 use crate::models::lights::GetLightsResponse;
 use crate::models::rooms::GetRoomsResponse;
+use crate::models::scenes::{GetScenesResponse, SceneRecallAction, SceneRecallRequest};
+use crate::models::GenericResponse;
 use crate::room::Rooms;
-use crate::{discover, http, models, Bridge, Light, Room};
+use crate::scene::Scenes;
+use crate::{discover, http, models, Bridge, Light, Room, Scene};
 
 // changes
 // - added support to get single light
 // - added support for getting rooms and control a grouped light
+// - added support for getting scenes
+// - added support for getting zones
+// - added support for the event stream
+// - verify the bridge certificate against the hue root and the bridge id
+// - share one http client instead of building one per request
+// - connect with a known bridge id so the first request is already pinned to it
 #[derive(Debug, Clone)]
 pub struct Hue {
     bridge: Bridge,
     device_type: DeviceType,
     application_key: Option<String>,
+    // shared by clones, including the ones held by lights, rooms and scenes
+    client: reqwest::Client,
 }
 
 impl Hue {
     pub async fn new(ip: Ipv4Addr, device_type: DeviceType) -> Result<Hue, HueError> {
-        let bridge = match Self::get_config(&ip).await {
-            Some(config) => Bridge::from((ip, config)),
-            None => return Err(HueError::Connection),
-        };
-
-        Ok(Hue {
-            bridge,
-            device_type,
-            application_key: None,
-        })
+        Self::connect(ip, device_type, None, None).await
     }
 
     pub async fn new_with_key(
@@ -43,15 +49,24 @@ impl Hue {
         device_type: DeviceType,
         application_key: String,
     ) -> Result<Hue, HueError> {
-        let bridge = match Self::get_config(&ip).await {
-            Some(config) => Bridge::from((ip, config)),
-            None => return Err(HueError::Connection),
-        };
+        Self::connect(ip, device_type, None, Some(application_key)).await
+    }
+
+    // connect reaches the bridge at ip. With bridge_id set only that bridge is trusted, from the
+    // first request on, otherwise any bridge signed by the hue root is.
+    pub async fn connect(
+        ip: Ipv4Addr,
+        device_type: DeviceType,
+        bridge_id: Option<&str>,
+        application_key: Option<String>,
+    ) -> Result<Hue, HueError> {
+        let bridge = Bridge::from((ip, Self::get_config(&ip, bridge_id).await?));
 
         Ok(Hue {
+            client: http::build(Some(&bridge.id))?,
             bridge,
             device_type,
-            application_key: Some(application_key),
+            application_key,
         })
     }
 
@@ -64,6 +79,10 @@ impl Hue {
             .unwrap()
     }
 
+    pub(crate) fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
     pub fn device_type(&self) -> &DeviceType {
         &self.device_type
     }
@@ -76,9 +95,9 @@ impl Hue {
         let ips = discover::discover(timeout).await;
         let mut bridges = Vec::new();
         for ip in ips {
-            match Self::get_config(&ip).await {
-                Some(config) => bridges.push(Bridge::from((ip, config))),
-                None => (),
+            match Self::get_config(&ip, None).await {
+                Ok(config) => bridges.push(Bridge::from((ip, config))),
+                Err(_) => (),
             }
         }
 
@@ -93,17 +112,20 @@ impl Hue {
         }
     }
 
-    async fn get_config(ip: &Ipv4Addr) -> Option<models::Config> {
+    // without a bridge_id the certificate is only checked against the hue root
+    async fn get_config(
+        ip: &Ipv4Addr,
+        bridge_id: Option<&str>,
+    ) -> Result<models::Config, HueError> {
         let url = Url::parse(format!("https://{}/api/0/config", ip.to_string()).as_str()).unwrap();
-        let client = http::build();
-        client
+        let client = http::build(bridge_id)?;
+        let config = client
             .get(url.to_string())
             .send()
-            .await
-            .ok()?
+            .await?
             .json::<models::Config>()
-            .await
-            .ok()
+            .await?;
+        Ok(config)
     }
 
     pub async fn authorize(&mut self) -> Result<(), HueError> {
@@ -113,7 +135,8 @@ impl Hue {
 
         let request = CreateUserRequest::new(self.device_type.clone());
 
-        let response = match http::build()
+        let response = match self
+            .client
             .post(self.url("api"))
             .json(&request)
             .send()
@@ -146,11 +169,8 @@ impl Hue {
     pub async fn lights(&self) -> Result<Lights, HueError> {
         self.check_authorization()?;
 
-        let response: GetLightsResponse = http::get_auth(
-            self.application_key.clone().unwrap(),
-            self.url("clip/v2/resource/light"),
-        )
-        .await?;
+        let response: GetLightsResponse =
+            http::get_auth(self, self.url("clip/v2/resource/light")).await?;
 
         if let Some(data) = response.data {
             return Ok(data
@@ -180,8 +200,7 @@ impl Hue {
         let url = format!("clip/v2/resource/{}/{}", resource, id);
         println!("{}", url);
 
-        let response: GetLightsResponse =
-            http::get_auth(self.application_key.clone().unwrap(), self.url(&url)).await?;
+        let response: GetLightsResponse = http::get_auth(self, self.url(&url)).await?;
         println!("{:?}", response);
 
         if let Some(mut data) = response.data {
@@ -198,16 +217,84 @@ impl Hue {
     }
 
     pub async fn rooms(&self) -> Result<Rooms, HueError> {
+        self.groups("room").await
+    }
+
+    // zones have the same shape as rooms, but can span lights from several rooms
+    pub async fn zones(&self) -> Result<Rooms, HueError> {
+        self.groups("zone").await
+    }
+
+    async fn groups(&self, resource: &str) -> Result<Rooms, HueError> {
+        self.check_authorization()?;
+
+        let response: GetRoomsResponse =
+            http::get_auth(self, self.url(&format!("clip/v2/resource/{}", resource))).await?;
+
+        if let Some(data) = response.data {
+            return Ok(data.into_iter().map(Room::new).collect());
+        }
+        if let Some(error) = response.error {
+            return Err(HueError::from(error.r#type.clone()));
+        }
+
+        Err(HueError::Unknown)
+    }
+
+    pub async fn entertainment_configurations(
+        &self,
+    ) -> Result<EntertainmentConfigurations, HueError> {
         self.check_authorization()?;
 
-        let response: GetRoomsResponse = http::get_auth(
-            self.application_key.clone().unwrap(),
-            self.url("clip/v2/resource/room"),
+        let response: GetEntertainmentConfigurationsResponse = http::get_auth(
+            self,
+            self.url("clip/v2/resource/entertainment_configuration"),
         )
         .await?;
 
         if let Some(data) = response.data {
-            return Ok(data.into_iter().map(|datum| Room::new(datum)).collect());
+            return Ok(data
+                .into_iter()
+                .map(EntertainmentConfiguration::new)
+                .collect());
+        }
+        if let Some(error) = response.error {
+            return Err(HueError::from(error.r#type.clone()));
+        }
+
+        Err(HueError::Unknown)
+    }
+
+    pub async fn scenes(&self) -> Result<Scenes, HueError> {
+        self.check_authorization()?;
+
+        let response: GetScenesResponse =
+            http::get_auth(self, self.url("clip/v2/resource/scene")).await?;
+
+        if let Some(data) = response.data {
+            return Ok(data
+                .into_iter()
+                .map(|datum| Scene::new(self, datum))
+                .collect());
+        }
+        if let Some(error) = response.error {
+            return Err(HueError::from(error.r#type.clone()));
+        }
+
+        Err(HueError::Unknown)
+    }
+
+    pub async fn scene_by_id(&self, id: String) -> Result<Scene, HueError> {
+        self.check_authorization()?;
+        let url = format!("clip/v2/resource/scene/{}", id);
+
+        let response: GetScenesResponse = http::get_auth(self, self.url(&url)).await?;
+
+        if let Some(mut data) = response.data {
+            if data.is_empty() {
+                return Err(HueError::Unknown);
+            }
+            return Ok(Scene::new(self, data.remove(0)));
         }
         if let Some(error) = response.error {
             return Err(HueError::from(error.r#type.clone()));
@@ -216,14 +303,41 @@ impl Hue {
         Err(HueError::Unknown)
     }
 
+    // recall_scene activates the scene with id without fetching it first, duration is the transition
+    // time in milliseconds
+    pub async fn recall_scene(
+        &self,
+        id: String,
+        action: SceneRecallAction,
+        duration: Option<u32>,
+    ) -> Result<(), HueError> {
+        self.check_authorization()?;
+        let url = self.url(format!("clip/v2/resource/scene/{}", id).as_str());
+        let request_payload = SceneRecallRequest::new(action, duration);
+
+        http::put_auth::<GenericResponse, SceneRecallRequest>(self, url, &request_payload)
+            .await
+            .map(|_| ())
+    }
+
+    // events subscribes to the bridge's event stream, the stream ends when the connection is lost
+    pub async fn events(&self) -> Result<impl Stream<Item = Result<Event, HueError>>, HueError> {
+        self.check_authorization()?;
+
+        let response = http::with_key(self, self.client.get(self.url("eventstream/clip/v2")))?
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(events::event_stream(response))
+    }
+
     pub async fn devices(&self) -> Result<Devices, HueError> {
         self.check_authorization()?;
 
-        let response: GetDevicesResponse = http::get_auth(
-            self.application_key.clone().unwrap(),
-            self.url("clip/v2/resource/device"),
-        )
-        .await?;
+        let response: GetDevicesResponse =
+            http::get_auth(self, self.url("clip/v2/resource/device")).await?;
 
         if let Some(data) = response.data {
             return Ok(data
@@ -238,3 +352,22 @@ impl Hue {
         Err(HueError::Unknown)
     }
 }
+
+#[cfg(test)]
+impl Hue {
+    // offline returns a hue that is never connected, for tests that don't send requests
+    pub(crate) fn offline() -> Hue {
+        Hue {
+            bridge: Bridge {
+                id: "001788fffe000000".to_string(),
+                model: crate::bridge::Model::BSB002,
+                version: "1948086000".to_string(),
+                address: Ipv4Addr::LOCALHOST,
+                supported: true,
+            },
+            device_type: DeviceType::new("test".to_string(), "test".to_string()).unwrap(),
+            application_key: None,
+            client: http::build(None).unwrap(),
+        }
+    }
+}
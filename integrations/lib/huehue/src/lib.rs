@@ -4,7 +4,7 @@
 //! unpredictable way.
 //!
 //! ## Features
-//! - Hue Bridge certificate validation.
+//! - Hue Bridge certificate validation, pinned to the bridge id.
 //! - Bridge discovery:
 //!   - through mDNS.
 //!   - through [discovery.meethue.com](https://discovery.meethue.com).
@@ -15,7 +15,19 @@
 //!   - switch on/off.
 //!   - color in the [CIE 1931 color space](https://en.wikipedia.org/wiki/CIE_1931_color_space).
 //!   - color in the sRGB color space.
+//!   - color temperature in mirek.
 //!   - dimming.
+//!   - transitions.
+//!   - several changes in a single request.
+//!   - alerts and effects.
+//! - Rooms and zones:
+//!   - list rooms and zones.
+//!   - control their grouped light.
+//! - Scenes:
+//!   - list scenes.
+//!   - recall a scene, optionally with a dynamic palette.
+//! - Events:
+//!   - stream light and scene updates from the bridge.
 //! - XY to RGB and RGB to XY conversion.
 //!
 //! ## Discovery
@@ -76,14 +88,19 @@ mod certificate;
 pub mod color;
 pub mod device;
 mod discover;
+pub mod entertainment;
+pub mod events;
 mod http;
 pub mod hue;
 pub mod light;
 pub mod models;
 pub mod room;
+pub mod scene;
+mod tls;
 
 pub use bridge::Bridge;
 pub use http::HueError;
 pub use hue::Hue;
-pub use light::Light;
+pub use light::{Light, LightUpdate};
 pub use room::Room;
+pub use scene::Scene;
@@ -1,15 +1,22 @@
-use crate::color::{Color, Component, Temperature, RGB8};
+use crate::color::{Color, Component, Gamut, MirekSchema, Temperature, RGB8};
 use crate::http::HueError;
 use crate::models::lights::{
-    GetLightsResponseItem, LightOnRequest, LightSetBrightnessRequest, LightSetColorRequest,
+    Dynamics, GetLightsResponseItem, LightAlertRequest, LightIdentifyRequest, LightOnRequest,
+    LightSetBrightnessRequest, LightSetBrightnessRequestBrightness, LightSetColorRequest,
+    LightSetColorRequestXY, LightSetColorTemperatureRequest, LightSetColorTemperatureRequestMirek,
+    LightSetEffectRequest, LightUpdateRequest, On,
 };
 use crate::models::GenericResponse;
 use crate::{http, Hue};
+use serde::Serialize;
 
 pub type Lights = Vec<Light>;
 
 // changes:
 // - added support for grouped light
+// - added support for color temperature
+// - added support for transitions, alerts and effects
+// - added support for updating several properties in one request
 
 #[derive(Debug, Clone)]
 pub enum LightResource {
@@ -23,7 +30,7 @@ impl LightResource {
             LightResource::GroupedLight => "grouped_light",
         }
     }
-    fn from_type(s: &str) -> LightResource {
+    pub(crate) fn from_type(s: &str) -> LightResource {
         match s {
             "light" => LightResource::Light,
             "grouped_light" => LightResource::GroupedLight,
@@ -32,16 +39,35 @@ impl LightResource {
     }
 }
 
+// LightUpdate is a set of changes applied together with Light::update, eg
+// LightUpdate { on: Some(true), brightness: Some(50.0), ..Default::default() }
+#[derive(Debug, Clone, Default)]
+pub struct LightUpdate {
+    pub on: Option<bool>,
+    pub brightness: Option<f32>,
+    pub color: Option<Component>,
+    // color temperature, can't be combined with color
+    pub mirek: Option<u32>,
+    // transition time in milliseconds, defaults to the light's transition_ms
+    pub transition: Option<u32>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Light {
     pub hue: Hue,
     pub resource: LightResource,
     pub id: uuid::Uuid,
     pub name: String,
+    // id of the device the light belongs to
+    pub owner: Option<uuid::Uuid>,
     pub on: bool,
     pub brightness: Option<f32>,
     pub color: Option<Color>,
     pub temperature: Option<Temperature>,
+    // effects the light supports, eg candle
+    pub effects: Option<Vec<String>>,
+    // transition time in milliseconds used for every following change
+    pub transition_ms: Option<u32>,
 }
 
 impl Light {
@@ -54,97 +80,307 @@ impl Light {
                 Some(metadata) => metadata.name.to_string(),
                 None => "".to_string(),
             },
+            owner: light.owner.map(|owner| owner.rid),
             on: light.on.on,
             brightness: light.dimming.map(|dimming| dimming.brightness),
             color: light.color,
             temperature: light.color_temperature,
+            effects: light.effects.map(|effects| effects.effect_values),
+            transition_ms: None,
         }
     }
 
-    pub async fn switch(&mut self, on: bool) -> Result<(), HueError> {
+    fn dynamics(&self) -> Option<Dynamics> {
+        self.transition_ms.map(|duration| Dynamics { duration })
+    }
+
+    async fn put<T: Serialize>(&self, request_payload: &T) -> Result<GenericResponse, HueError> {
         let url = self
             .hue
             .url(format!("clip/v2/resource/{}/{}", self.resource.endpoint(), self.id).as_str());
-        let application_key = self.hue.application_key().clone().unwrap();
-        let request_payload = LightOnRequest::new(on);
-
-        match http::put_auth::<GenericResponse, LightOnRequest>(
-            application_key,
-            url,
-            &request_payload,
-        )
-        .await
-        {
-            Ok(_) => {
-                self.on = on;
-                Ok(())
-            }
-            Err(e) => Err(e),
-        }
+
+        http::put_auth::<GenericResponse, T>(&self.hue, url, request_payload).await
+    }
+
+    pub async fn switch(&mut self, on: bool) -> Result<(), HueError> {
+        self.put(&LightOnRequest::new(on, self.dynamics())).await?;
+        self.on = on;
+        Ok(())
+    }
+
+    // is_group is true for grouped lights, the bridge doesn't report their color capabilities but
+    // passes color changes on to every light in the group that supports them
+    pub fn is_group(&self) -> bool {
+        matches!(self.resource, LightResource::GroupedLight)
     }
 
     pub async fn set_color(&mut self, component: Component) -> Result<(), HueError> {
-        if self.color.is_none() {
+        if self.color.is_none() && !self.is_group() {
             return Err(HueError::Unsupported);
         }
 
-        let url = self
-            .hue
-            .url(format!("clip/v2/resource/{}/{}", self.resource.endpoint(), self.id).as_str());
-        let application_key = self.hue.application_key().clone().unwrap();
-        let request_payload = LightSetColorRequest::new(component.clone());
-
-        match http::put_auth::<GenericResponse, LightSetColorRequest>(
-            application_key,
-            url,
-            &request_payload,
-        )
-        .await
-        {
-            Ok(_) => {
-                if let Some(color) = &mut self.color {
-                    color.xy = component;
-                }
-                Ok(())
-            }
-            Err(e) => Err(e),
+        self.put(&LightSetColorRequest::new(
+            component.clone(),
+            self.dynamics(),
+        ))
+        .await?;
+        if let Some(color) = &mut self.color {
+            color.xy = component;
         }
+        Ok(())
     }
 
-    pub async fn set_color_rgb(&mut self, rgb: RGB8) -> Result<(), HueError> {
+    // xy_from_rgb converts rgb to the closest color in the light's gamut
+    pub fn xy_from_rgb(&self, rgb: RGB8) -> Result<Component, HueError> {
         if let Some(color) = &self.color {
-            let xy = color.gamut.xy_from_rgb8(rgb);
-            self.set_color(xy).await
+            Ok(color.gamut.xy_from_rgb8(rgb))
+        } else if self.is_group() {
+            Ok(Gamut::gamut_c().xy_from_rgb8(rgb))
         } else {
             Err(HueError::Unsupported)
         }
     }
 
+    pub async fn set_color_rgb(&mut self, rgb: RGB8) -> Result<(), HueError> {
+        let xy = self.xy_from_rgb(rgb)?;
+        self.set_color(xy).await
+    }
+
+    fn clamp_mirek(&self, mirek: u32) -> Result<u32, HueError> {
+        match &self.temperature {
+            Some(temperature) => Ok(temperature.mirek_schema.clamp(mirek)),
+            None if self.is_group() => Ok(MirekSchema::default().clamp(mirek)),
+            None => Err(HueError::Unsupported),
+        }
+    }
+
+    // set_mirek sets the color temperature, clamped to the range the light supports
+    pub async fn set_mirek(&mut self, mirek: u32) -> Result<(), HueError> {
+        let mirek = self.clamp_mirek(mirek)?;
+
+        self.put(&LightSetColorTemperatureRequest::new(
+            mirek,
+            self.dynamics(),
+        ))
+        .await?;
+        if let Some(temperature) = &mut self.temperature {
+            temperature.mirek = Some(mirek);
+            temperature.mirek_valid = true;
+        }
+        Ok(())
+    }
+
     pub async fn dimm(&mut self, value: f32) -> Result<(), HueError> {
         if self.brightness.is_none() {
             return Err(HueError::Unsupported);
         }
 
-        let url = self
-            .hue
-            .url(format!("clip/v2/resource/{}/{}", self.resource.endpoint(), self.id).as_str());
-        let application_key = self.hue.application_key().clone().unwrap();
-        let request_payload = LightSetBrightnessRequest::new(value.clone());
-
-        match http::put_auth::<GenericResponse, LightSetBrightnessRequest>(
-            application_key,
-            url,
-            &request_payload,
-        )
-        .await
+        self.put(&LightSetBrightnessRequest::new(value, self.dynamics()))
+            .await?;
+        if let Some(brightness) = &mut self.brightness {
+            *brightness = value;
+        }
+        Ok(())
+    }
+
+    // update applies all changes in a single request, so they take effect at the same time
+    pub async fn update(&mut self, update: LightUpdate) -> Result<(), HueError> {
+        let request_payload = self.update_request(&update)?;
+        self.put(&request_payload).await?;
+        self.apply_update(&request_payload);
+        Ok(())
+    }
+
+    // update_request checks the update against what the light supports and builds its request
+    fn update_request(&self, update: &LightUpdate) -> Result<LightUpdateRequest, HueError> {
+        if update.color.is_some() && update.mirek.is_some() {
+            return Err(HueError::Unsupported);
+        }
+        if update.brightness.is_some() && self.brightness.is_none() {
+            return Err(HueError::Unsupported);
+        }
+        if update.color.is_some() && self.color.is_none() && !self.is_group() {
+            return Err(HueError::Unsupported);
+        }
+        let mirek = update
+            .mirek
+            .map(|mirek| self.clamp_mirek(mirek))
+            .transpose()?;
+
+        Ok(LightUpdateRequest {
+            on: update.on.map(|on| On { on }),
+            dimming: update
+                .brightness
+                .map(|brightness| LightSetBrightnessRequestBrightness { brightness }),
+            color: update.color.clone().map(|xy| LightSetColorRequestXY { xy }),
+            color_temperature: mirek.map(|mirek| LightSetColorTemperatureRequestMirek { mirek }),
+            dynamics: update
+                .transition
+                .map(|duration| Dynamics { duration })
+                .or_else(|| self.dynamics()),
+        })
+    }
+
+    // apply_update keeps the light's state in line with a request the bridge accepted
+    fn apply_update(&mut self, request: &LightUpdateRequest) {
+        if let Some(on) = &request.on {
+            self.on = on.on;
+        }
+        if let (Some(brightness), Some(dimming)) = (&mut self.brightness, &request.dimming) {
+            *brightness = dimming.brightness;
+        }
+        if let (Some(color), Some(request_color)) = (&mut self.color, &request.color) {
+            color.xy = request_color.xy.clone();
+        }
+        if let (Some(temperature), Some(request_temperature)) =
+            (&mut self.temperature, &request.color_temperature)
         {
-            Ok(_) => {
-                if let Some(brightness) = &mut self.brightness {
-                    *brightness = value;
-                }
-                Ok(())
-            }
-            Err(e) => Err(e),
+            temperature.mirek = Some(request_temperature.mirek);
+            temperature.mirek_valid = true;
         }
     }
+
+    // alert makes the light breathe once
+    pub async fn alert(&self) -> Result<(), HueError> {
+        self.put(&LightAlertRequest::breathe()).await.map(|_| ())
+    }
+
+    // identify blinks the light so it can be found, only single lights support it
+    pub async fn identify(&self) -> Result<(), HueError> {
+        if matches!(self.resource, LightResource::GroupedLight) {
+            return Err(HueError::Unsupported);
+        }
+
+        self.put(&LightIdentifyRequest::identify())
+            .await
+            .map(|_| ())
+    }
+
+    // set_effect starts one of the light's effects, no_effect stops it. Only single lights have
+    // effects, see effects for the ones a light supports.
+    pub async fn set_effect(&self, effect: &str) -> Result<(), HueError> {
+        if self.is_group() {
+            return Err(HueError::Unsupported);
+        }
+
+        self.put(&LightSetEffectRequest::new(effect.to_owned()))
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(light: serde_json::Value) -> Light {
+        Light::new(&Hue::offline(), serde_json::from_value(light).unwrap())
+    }
+
+    fn color_light() -> Light {
+        light(serde_json::json!({
+            "type": "light",
+            "id": "4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e",
+            "on": { "on": false },
+            "dimming": { "brightness": 20.0 },
+            "color": {
+                "gamut": {
+                    "red": { "x": 0.6915, "y": 0.3083 },
+                    "green": { "x": 0.17, "y": 0.7 },
+                    "blue": { "x": 0.1532, "y": 0.0475 },
+                },
+                "gamut_type": "C",
+                "xy": { "x": 0.3, "y": 0.3 },
+            },
+            "color_temperature": {
+                "mirek": 300,
+                "mirek_schema": { "mirek_maximum": 454, "mirek_minimum": 153 },
+                "mirek_valid": true,
+            },
+        }))
+    }
+
+    fn on_off_light() -> Light {
+        light(serde_json::json!({
+            "type": "light",
+            "id": "9a1c2b3d-4e5f-4a6b-8c7d-0e1f2a3b4c5d",
+            "on": { "on": true },
+        }))
+    }
+
+    #[test]
+    fn update_rejects_color_with_mirek() {
+        let update = LightUpdate {
+            color: Component::new(0.4, 0.4),
+            mirek: Some(300),
+            ..Default::default()
+        };
+        assert!(matches!(
+            color_light().update_request(&update),
+            Err(HueError::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn update_rejects_unsupported_changes() {
+        let light = on_off_light();
+        let brightness = LightUpdate {
+            brightness: Some(50.0),
+            ..Default::default()
+        };
+        let color = LightUpdate {
+            color: Component::new(0.4, 0.4),
+            ..Default::default()
+        };
+        let mirek = LightUpdate {
+            mirek: Some(300),
+            ..Default::default()
+        };
+
+        for update in [brightness, color, mirek] {
+            assert!(matches!(
+                light.update_request(&update),
+                Err(HueError::Unsupported)
+            ));
+        }
+        assert!(light
+            .update_request(&LightUpdate {
+                on: Some(false),
+                ..Default::default()
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn update_applies_changes_locally() {
+        let mut light = color_light();
+        let update = LightUpdate {
+            on: Some(true),
+            brightness: Some(80.0),
+            // above the light's maximum
+            mirek: Some(600),
+            transition: Some(400),
+            ..Default::default()
+        };
+
+        let request = light.update_request(&update).unwrap();
+        assert_eq!(request.color_temperature.as_ref().unwrap().mirek, 454);
+        assert_eq!(request.dynamics.as_ref().unwrap().duration, 400);
+        light.apply_update(&request);
+
+        assert!(light.on);
+        assert_eq!(light.brightness, Some(80.0));
+        assert_eq!(light.temperature.as_ref().unwrap().mirek, Some(454));
+        // the color isn't touched while changing the temperature
+        assert_eq!(light.color.as_ref().unwrap().xy.x, 0.3);
+
+        let update = LightUpdate {
+            color: Component::new(0.4, 0.5),
+            ..Default::default()
+        };
+        let request = light.update_request(&update).unwrap();
+        light.apply_update(&request);
+        assert_eq!(light.color.as_ref().unwrap().xy.y, 0.5);
+        assert!(light.on);
+    }
 }
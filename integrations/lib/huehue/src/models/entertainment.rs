@@ -0,0 +1,22 @@
+use crate::models::generic::GenericIdentifier;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetEntertainmentConfigurationsResponse {
+    pub data: Option<Vec<GetEntertainmentConfigurationsResponseItem>>,
+    pub error: Option<crate::models::Error>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetEntertainmentConfigurationsResponseItem {
+    pub id: uuid::Uuid,
+    pub metadata: EntertainmentMetadata,
+    // lights that take part in the entertainment area
+    pub light_services: Option<Vec<GenericIdentifier>>,
+}
+
+// unlike other resources entertainment areas have no archetype
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntertainmentMetadata {
+    pub name: String,
+}
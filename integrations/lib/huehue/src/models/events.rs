@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use crate::color::Component;
+use crate::models::lights::{Dimming, On};
+
+// EventContainer is one entry of the json array sent in each event stream message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventContainer {
+    #[serde(rename = "type")]
+    pub r#type: String,
+    // kept as json so a resource that can't be parsed only skips itself
+    pub data: Vec<serde_json::Value>,
+}
+
+// EventResource only contains the fields that changed, which depend on the resource type, so they
+// are kept as json until the type is known
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventResource {
+    #[serde(rename = "type")]
+    pub r#type: String,
+    pub id: uuid::Uuid,
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightEventFields {
+    pub on: Option<On>,
+    pub dimming: Option<Dimming>,
+    pub color: Option<LightEventColor>,
+    pub color_temperature: Option<LightEventColorTemperature>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightEventColor {
+    pub xy: Component,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightEventColorTemperature {
+    // null while the light is in color mode
+    pub mirek: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneEventFields {
+    pub status: Option<SceneEventStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneEventStatus {
+    // inactive, static or dynamic_palette
+    pub active: String,
+}
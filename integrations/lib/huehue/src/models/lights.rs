@@ -26,6 +26,8 @@ pub struct GetLightsResponseItem {
 
     pub id: uuid::Uuid,
     pub metadata: Option<super::generic::Metadata>,
+    // device the light belongs to, rooms list devices rather than lights
+    pub owner: Option<super::generic::GenericIdentifier>,
     pub dimming: Option<Dimming>,
     pub on: On,
 
@@ -37,6 +39,21 @@ pub struct GetLightsResponseItem {
     #[serde(default)]
     #[serde(deserialize_with = "object_empty_as_none")]
     pub color_temperature: Option<Temperature>,
+    #[serde(default)]
+    #[serde(deserialize_with = "object_empty_as_none")]
+    pub effects: Option<Effects>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Effects {
+    pub effect_values: Vec<String>,
+    pub status: Option<String>,
+}
+
+// Dynamics controls how a change is applied, duration is the transition time in milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dynamics {
+    pub duration: u32,
 }
 
 pub fn object_empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
@@ -65,6 +82,8 @@ where
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LightOnRequest {
     pub on: On,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<Dynamics>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,6 +94,20 @@ pub struct LightSetColorRequestXY {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LightSetColorRequest {
     pub color: LightSetColorRequestXY,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<Dynamics>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSetColorTemperatureRequestMirek {
+    pub mirek: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSetColorTemperatureRequest {
+    pub color_temperature: LightSetColorTemperatureRequestMirek,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<Dynamics>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,28 +118,137 @@ pub struct LightSetBrightnessRequestBrightness {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LightSetBrightnessRequest {
     pub dimming: LightSetBrightnessRequestBrightness,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<Dynamics>,
+}
+
+// LightUpdateRequest combines any of the changes above into a single request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LightUpdateRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<On>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimming: Option<LightSetBrightnessRequestBrightness>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<LightSetColorRequestXY>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_temperature: Option<LightSetColorTemperatureRequestMirek>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<Dynamics>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightRequestAction {
+    pub action: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightAlertRequest {
+    pub alert: LightRequestAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightIdentifyRequest {
+    pub identify: LightRequestAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSetEffectRequestEffect {
+    pub effect: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSetEffectRequest {
+    pub effects: LightSetEffectRequestEffect,
 }
 
 impl LightOnRequest {
-    pub fn new(on: bool) -> LightOnRequest {
-        LightOnRequest { on: On { on } }
+    pub fn new(on: bool, dynamics: Option<Dynamics>) -> LightOnRequest {
+        LightOnRequest {
+            on: On { on },
+            dynamics,
+        }
     }
 }
 
 impl LightSetColorRequest {
-    pub fn new(color: Component) -> LightSetColorRequest {
+    pub fn new(color: Component, dynamics: Option<Dynamics>) -> LightSetColorRequest {
         LightSetColorRequest {
             color: LightSetColorRequestXY { xy: color },
+            dynamics,
+        }
+    }
+}
+
+impl LightSetColorTemperatureRequest {
+    pub fn new(mirek: u32, dynamics: Option<Dynamics>) -> LightSetColorTemperatureRequest {
+        LightSetColorTemperatureRequest {
+            color_temperature: LightSetColorTemperatureRequestMirek { mirek },
+            dynamics,
         }
     }
 }
 
 impl LightSetBrightnessRequest {
-    pub fn new(brightness: f32) -> LightSetBrightnessRequest {
+    pub fn new(brightness: f32, dynamics: Option<Dynamics>) -> LightSetBrightnessRequest {
         LightSetBrightnessRequest {
             dimming: LightSetBrightnessRequestBrightness {
-                brightness: brightness.max(0.0).min(100.0),
+                brightness: brightness.clamp(0.0, 100.0),
+            },
+            dynamics,
+        }
+    }
+}
+
+impl LightAlertRequest {
+    // breathe is the only alert the bridge supports
+    pub fn breathe() -> LightAlertRequest {
+        LightAlertRequest {
+            alert: LightRequestAction {
+                action: "breathe".to_owned(),
             },
         }
     }
 }
+
+impl LightIdentifyRequest {
+    pub fn identify() -> LightIdentifyRequest {
+        LightIdentifyRequest {
+            identify: LightRequestAction {
+                action: "identify".to_owned(),
+            },
+        }
+    }
+}
+
+impl LightSetEffectRequest {
+    pub fn new(effect: String) -> LightSetEffectRequest {
+        LightSetEffectRequest {
+            effects: LightSetEffectRequestEffect { effect },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_request_only_sends_changes() {
+        let request = LightUpdateRequest {
+            on: Some(On { on: true }),
+            dimming: Some(LightSetBrightnessRequestBrightness { brightness: 50.0 }),
+            dynamics: Some(Dynamics { duration: 400 }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "on": { "on": true },
+                "dimming": { "brightness": 50.0 },
+                "dynamics": { "duration": 400 },
+            })
+        );
+    }
+}
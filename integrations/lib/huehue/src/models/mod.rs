@@ -2,10 +2,13 @@ pub mod config;
 pub mod create_user;
 pub mod device_type;
 pub mod devices;
+pub mod entertainment;
 pub mod error;
+pub mod events;
 pub mod generic;
 pub mod lights;
 pub mod rooms;
+pub mod scenes;
 
 pub use config::Config;
 pub use error::Error;
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::generic::GenericIdentifier;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetScenesResponse {
+    pub data: Option<Vec<GetScenesResponseItem>>,
+    pub error: Option<crate::models::Error>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetScenesResponseItem {
+    #[serde(rename = "type")]
+    pub r#type: String,
+
+    pub id: uuid::Uuid,
+    pub metadata: SceneMetadata,
+    // the room or zone the scene belongs to
+    pub group: GenericIdentifier,
+}
+
+// scenes have no archetype, so they don't use the generic metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneMetadata {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SceneRecallAction {
+    Active,
+    DynamicPalette,
+    Static,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneRecall {
+    pub action: SceneRecallAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneRecallRequest {
+    pub recall: SceneRecall,
+}
+
+impl SceneRecallRequest {
+    pub fn new(action: SceneRecallAction, duration: Option<u32>) -> SceneRecallRequest {
+        SceneRecallRequest {
+            recall: SceneRecall { action, duration },
+        }
+    }
+}
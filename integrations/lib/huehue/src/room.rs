@@ -3,6 +3,7 @@ use crate::models::rooms::GetRoomsResponseItem;
 
 pub type Rooms = Vec<Room>;
 
+// Room is also used for zones, which have the same fields
 pub struct Room {
     pub id: uuid::Uuid,
     pub name: String,
@@ -0,0 +1,36 @@
+use crate::http::HueError;
+use crate::models::generic::GenericIdentifier;
+use crate::models::scenes::{GetScenesResponseItem, SceneRecallAction};
+use crate::Hue;
+
+pub type Scenes = Vec<Scene>;
+
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub hue: Hue,
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub group: GenericIdentifier,
+}
+
+impl Scene {
+    pub fn new(hue: &Hue, scene: GetScenesResponseItem) -> Scene {
+        Scene {
+            hue: hue.clone(),
+            id: scene.id,
+            name: scene.metadata.name,
+            group: scene.group,
+        }
+    }
+
+    // recall activates the scene, duration is the transition time in milliseconds
+    pub async fn recall(
+        &self,
+        action: SceneRecallAction,
+        duration: Option<u32>,
+    ) -> Result<(), HueError> {
+        self.hue
+            .recall_scene(self.id.to_string(), action, duration)
+            .await
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, Error, ServerName};
+
+use crate::certificate::CERTIFICATE;
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+];
+
+// Bridges are addressed by ip and their certificate has the bridge id as its common name instead of
+// a hostname, so the default verifier can't check them. BridgeVerifier checks the chain against the
+// Signify root and, once the bridge id is known, that the common name matches it.
+struct BridgeVerifier {
+    root: Vec<u8>,
+    bridge_id: Option<String>,
+}
+
+impl BridgeVerifier {
+    fn new(bridge_id: Option<&str>) -> BridgeVerifier {
+        let root = rustls_pemfile::certs(&mut CERTIFICATE.as_bytes())
+            .ok()
+            .and_then(|certs| certs.into_iter().next())
+            .expect("bundled hue root certificate is valid");
+
+        BridgeVerifier {
+            root,
+            bridge_id: bridge_id.map(str::to_owned),
+        }
+    }
+
+    fn verify_common_name(&self, end_entity: &Certificate) -> Result<(), Error> {
+        let bridge_id = match &self.bridge_id {
+            Some(bridge_id) => bridge_id,
+            None => return Ok(()),
+        };
+
+        let (_, cert) = x509_parser::parse_x509_certificate(&end_entity.0)
+            .map_err(|_| Error::InvalidCertificateEncoding)?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok())
+            .unwrap_or_default();
+
+        if common_name.eq_ignore_ascii_case(bridge_id) {
+            Ok(())
+        } else {
+            Err(Error::InvalidCertificateData(format!(
+                "certificate is for {:?}, expected bridge {}",
+                common_name, bridge_id
+            )))
+        }
+    }
+}
+
+impl ServerCertVerifier for BridgeVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let anchor = webpki::TrustAnchor::try_from_cert_der(&self.root)
+            .map_err(|err| Error::General(format!("invalid hue root certificate: {}", err)))?;
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref())
+            .map_err(|_| Error::InvalidCertificateEncoding)?;
+        let intermediates: Vec<&[u8]> = intermediates.iter().map(|cert| cert.0.as_ref()).collect();
+        let time = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+
+        cert.verify_is_valid_tls_server_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TlsServerTrustAnchors(&[anchor]),
+            &intermediates,
+            time,
+        )
+        .map_err(|err| {
+            Error::InvalidCertificateData(format!("not signed by the hue root: {}", err))
+        })?;
+        self.verify_common_name(end_entity)?;
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// client_config only trusts bridges, pinned to bridge_id when it is set
+pub fn client_config(bridge_id: Option<&str>) -> ClientConfig {
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(BridgeVerifier::new(bridge_id)))
+        .with_no_client_auth()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_name_pinned_to_bridge_id() {
+        // the root's common name stands in for a bridge certificate
+        let root = Certificate(BridgeVerifier::new(None).root);
+
+        assert!(BridgeVerifier::new(None).verify_common_name(&root).is_ok());
+        assert!(BridgeVerifier::new(Some("ROOT-BRIDGE"))
+            .verify_common_name(&root)
+            .is_ok());
+        assert!(BridgeVerifier::new(Some("001788fffe23bfc2"))
+            .verify_common_name(&root)
+            .is_err());
+    }
+}
@@ -1,11 +1,18 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
 use tokio::process::Command;
+use tokio::sync::Mutex;
 use tracing::info;
 
 use crate::integrations::integration;
 
+// profile rules and buttons poll the playing state, reuse it for a while so every poll doesn't reach
+// the device or start atvremote
+const PLAYING_CACHE_SEC: u64 = 15;
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
 pub enum Protocol {
     #[serde(rename = "companion")]
@@ -53,20 +60,25 @@ impl integration::IntegrationConfig for IntegrationConfig {
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 struct CommandAction {
     device: String,
     command: String,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 struct OpenAppAction {
     device: String,
     identifier: String,
 }
 
-// mayber use #[serde(untagged)] for this?
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct StateQuery {
+    device: String,
+}
+
+// mayber use #[serde(untagged)] for this?
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(tag = "action")]
 enum Actions {
     #[serde(rename = "command")]
@@ -80,6 +92,16 @@ pub struct Integration {
     binary: Option<String>,
     atv_api_endpoint: Option<String>,
     devices: HashMap<String, Device>,
+    // shared so requests reuse connections to the api
+    client: reqwest::Client,
+    // device name -> last playing state
+    playing: Mutex<HashMap<String, CachedPlaying>>,
+}
+
+// CachedPlaying also keeps errors, so an unreachable device isn't retried on every poll
+struct CachedPlaying {
+    fetched: Instant,
+    result: Result<serde_json::value::Value, String>,
 }
 
 impl Integration {
@@ -100,29 +122,39 @@ impl Integration {
             binary: Some("atvremote".to_string()),
             atv_api_endpoint: Some(atv_api_endpoint.to_string()),
             devices: devices_map,
+            client: reqwest::Client::new(),
+            playing: Mutex::default(),
         });
     }
 
-    async fn run_atvremote_command(&self, options: Actions) -> Result<()> {
+    // api_or_binary runs run_api with the api endpoint, falling back to run_binary with atvremote
+    // when the api is disabled or fails
+    async fn api_or_binary<T, A, AF, B, BF>(&self, run_api: A, run_binary: B) -> Result<T>
+    where
+        A: FnOnce(String) -> AF,
+        AF: Future<Output = Result<T>>,
+        B: FnOnce(String) -> BF,
+        BF: Future<Output = Result<T>>,
+    {
         let api_result = match &self.atv_api_endpoint {
-            Some(atv_api_endpoint) => {
-                self.run_atvremote_command_api(&options, atv_api_endpoint.to_string())
-                    .await
-            }
+            Some(atv_api_endpoint) => run_api(atv_api_endpoint.to_string()).await,
             None => Err(anyhow!("airplay: both binary and api are disabled")),
         };
-        info!("{:?}", &api_result);
-        if api_result.is_ok() {
-            return api_result;
-        }
-
-        if let Some(binary) = &self.binary {
-            return self
-                .run_atvremote_command_binary(&options, binary.to_string())
-                .await;
+        match (&api_result, &self.binary) {
+            (Ok(_), _) | (Err(_), None) => api_result,
+            (Err(err), Some(binary)) => {
+                info!(error = ?err, "airplay api failed, falling back to atvremote");
+                run_binary(binary.to_string()).await
+            }
         }
+    }
 
-        return api_result;
+    async fn run_atvremote_command(&self, options: Actions) -> Result<()> {
+        self.api_or_binary(
+            |atv_api_endpoint| self.run_atvremote_command_api(&options, atv_api_endpoint),
+            |binary| self.run_atvremote_command_binary(&options, binary),
+        )
+        .await
     }
 
     async fn run_atvremote_command_api(
@@ -131,8 +163,29 @@ impl Integration {
         atv_api_endpoint: String,
     ) -> Result<()> {
         let (device, endpoint) = self.get_url_for_action(&options)?;
-        let client = reqwest::Client::new();
-        let mut r = client.get(format!("{}/{}", atv_api_endpoint, endpoint));
+        let response = self
+            .api_request(device, &atv_api_endpoint, &endpoint)
+            .send()
+            .await?;
+        if response.status() != 200 {
+            return Err(anyhow!(
+                "failed to run airplay command: {:?}",
+                response.text().await.unwrap_or("".to_string())
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn api_request(
+        &self,
+        device: &Device,
+        atv_api_endpoint: &str,
+        endpoint: &str,
+    ) -> reqwest::RequestBuilder {
+        let mut r = self
+            .client
+            .get(format!("{}/{}", atv_api_endpoint, endpoint));
 
         let default_protocol = Protocol::AirPlay;
         let protocol = device
@@ -150,15 +203,84 @@ impl Integration {
             r = r.header(format!("{}-credentials", &protocol), credentials);
         }
 
-        let response = r.send().await?;
+        r
+    }
+
+    // get_playing returns what is currently playing on the device, eg {"device_state": "Playing", "title": "..."}
+    async fn get_playing(&self, device_name: &str) -> Result<serde_json::value::Value> {
+        let device = self
+            .devices
+            .get(device_name)
+            .ok_or_else(|| anyhow!("unable to find device named {}", device_name))?;
+
+        // held while fetching, so polls that come in meanwhile wait for the result
+        let mut playing = self.playing.lock().await;
+        if let Some(cached) = playing.get(device_name) {
+            if cached.fetched.elapsed() < Duration::from_secs(PLAYING_CACHE_SEC) {
+                return cached.result.clone().map_err(|err| anyhow!(err));
+            }
+        }
+
+        let result = self
+            .api_or_binary(
+                |atv_api_endpoint| self.get_playing_api(device, atv_api_endpoint),
+                |binary| self.get_playing_binary(device, binary),
+            )
+            .await;
+        playing.insert(
+            device_name.to_string(),
+            CachedPlaying {
+                fetched: Instant::now(),
+                result: result.as_ref().cloned().map_err(|err| err.to_string()),
+            },
+        );
+        result
+    }
+
+    async fn get_playing_api(
+        &self,
+        device: &Device,
+        atv_api_endpoint: String,
+    ) -> Result<serde_json::value::Value> {
+        let response = self
+            .api_request(
+                device,
+                &atv_api_endpoint,
+                &format!("playing/{}", device.identifier),
+            )
+            .send()
+            .await?;
         if response.status() != 200 {
             return Err(anyhow!(
-                "failed to run airplay command: {:?}",
+                "failed to get airplay playing state: {:?}",
                 response.text().await.unwrap_or("".to_string())
             ));
         }
 
-        Ok(())
+        Ok(response.json().await?)
+    }
+
+    async fn get_playing_binary(
+        &self,
+        device: &Device,
+        binary: String,
+    ) -> Result<serde_json::value::Value> {
+        let mut cmd = self.atvremote_command_for_device(device, binary).await;
+        cmd.arg("playing");
+
+        let output = cmd
+            .output()
+            .await
+            .map_err(|e| anyhow!("airplay playing command failed: {}", e))?;
+        if output.status.code() != Some(0) {
+            return Err(anyhow!(
+                "airplay playing command returned non-zero exit ({}): {}",
+                output.status,
+                String::from_utf8(output.stderr)?,
+            ));
+        }
+
+        Ok(parse_playing_output(&String::from_utf8(output.stdout)?))
     }
 
     fn get_url_for_action(&self, action: &Actions) -> Result<(&Device, String)> {
@@ -250,6 +372,23 @@ impl Integration {
     }
 }
 
+// parse_playing_output converts the "Key: Value" lines printed by atvremote playing into an object
+// with snake case keys
+fn parse_playing_output(output: &str) -> serde_json::value::Value {
+    let state: serde_json::Map<String, serde_json::value::Value> = output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| {
+            (
+                key.trim().to_lowercase().replace(' ', "_"),
+                serde_json::value::Value::String(value.trim().to_string()),
+            )
+        })
+        .collect();
+
+    serde_json::value::Value::Object(state)
+}
+
 #[async_trait]
 impl integration::Integration for Integration {
     fn name(&self) -> &str {
@@ -270,4 +409,21 @@ impl integration::Integration for Integration {
         })?;
         self.run_atvremote_command(options).await
     }
+
+    async fn describe(&self) -> integration::IntegrationDescription {
+        integration::IntegrationDescription {
+            name: self.name.to_string(),
+            actions: schemars::schema_for!(Actions),
+            entities: HashMap::from([(
+                "devices".to_string(),
+                integration::sorted_names(self.devices.keys()),
+            )]),
+        }
+    }
+
+    async fn get_state(&self, query: serde_json::value::Value) -> Result<serde_json::value::Value> {
+        let query: StateQuery = serde_json::from_value(query)
+            .map_err(|err| anyhow!("invalid {} state query: {:?}", self.name(), err))?;
+        self.get_playing(&query.device).await
+    }
 }
@@ -2,9 +2,12 @@ use std::collections::HashMap;
 
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use tokio::sync::RwLock;
+use tracing::info;
 
 use crate::homebridge::{Homebridge, HomebridgeDevice};
 use crate::integrations::integration;
+use crate::integrations::utils::name_sync::NameSync;
 
 const DEFAULT_NAME: &str = "homebridge";
 
@@ -32,19 +35,19 @@ impl integration::IntegrationConfig for IntegrationConfig {
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 struct BaseAction {
     uuid: Option<String>,
     device: Option<String>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 struct BrightnessAction {
     brightness: Option<f32>,
     rel_brightness: Option<f32>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 struct ToggleAction {
     #[serde(flatten)]
     base_action: BaseAction,
@@ -52,7 +55,7 @@ struct ToggleAction {
     brightness_action: BrightnessAction,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 struct SetAction {
     #[serde(flatten)]
     base_action: BaseAction,
@@ -61,7 +64,7 @@ struct SetAction {
     on: Option<bool>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(tag = "action")]
 enum Actions {
     #[serde(rename = "toggle")]
@@ -73,7 +76,9 @@ enum Actions {
 pub struct Integration {
     name: String,
     homebridge: Homebridge,
-    device_name_to_id: HashMap<String, String>,
+    // refreshed in the background and on lookup misses, so accessories can be added without a restart
+    device_name_to_id: RwLock<HashMap<String, String>>,
+    name_sync: NameSync,
 }
 
 impl Integration {
@@ -85,22 +90,30 @@ impl Integration {
     ) -> Result<Integration> {
         let homebridge = Homebridge::new(endpoint, username, password).await?;
 
-        let devices = homebridge.devices().await?;
-        let mut integration = Integration {
+        let integration = Integration {
             name: name,
             homebridge: homebridge,
-            device_name_to_id: HashMap::new(),
+            device_name_to_id: RwLock::new(HashMap::new()),
+            name_sync: NameSync::default(),
         };
+        integration.sync().await?;
 
-        integration.device_name_to_id.clear();
+        Ok(integration)
+    }
 
-        for device in devices {
-            integration
-                .device_name_to_id
-                .insert(device.name(), device.unique_id());
-        }
+    async fn sync(&self) -> Result<()> {
+        self.name_sync.sync(self.load_names()).await
+    }
+
+    async fn load_names(&self) -> Result<()> {
+        let devices = self.homebridge.devices().await?;
+        let device_name_to_id = devices
+            .iter()
+            .map(|device| (device.name(), device.unique_id()))
+            .collect();
 
-        return Ok(integration);
+        *self.device_name_to_id.write().await = device_name_to_id;
+        Ok(())
     }
 
     async fn get_device_by_name_or_id(
@@ -127,11 +140,19 @@ impl Integration {
 
     async fn get_device_by_name(&self, name: &str) -> Result<HomebridgeDevice> {
         let id = self
-            .device_name_to_id
-            .get(name)
-            .context(format!("unable to find device named: {}", name))?;
+            .name_sync
+            .lookup_or_sync(
+                name,
+                || async { self.device_name_to_id.read().await.get(name).cloned() },
+                async {
+                    info!(name, "homebridge device not found, resyncing");
+                    self.load_names().await
+                },
+            )
+            .await?;
+        let id = id.context(format!("unable to find device named: {}", name))?;
 
-        return self.homebridge.device_by_id(id.to_string()).await;
+        return self.homebridge.device_by_id(id).await;
     }
 
     async fn set_device(&self, device: &mut HomebridgeDevice, action: &SetAction) -> Result<()> {
@@ -233,4 +254,34 @@ impl integration::Integration for Integration {
             }
         }
     }
+
+    async fn describe(&self) -> integration::IntegrationDescription {
+        integration::IntegrationDescription {
+            name: self.name.to_string(),
+            actions: schemars::schema_for!(Actions),
+            entities: HashMap::from([(
+                "devices".to_string(),
+                integration::sorted_names(self.device_name_to_id.read().await.keys()),
+            )]),
+        }
+    }
+
+    async fn refresh(&self) -> Result<()> {
+        self.sync().await
+    }
+
+    async fn get_state(&self, query: serde_json::value::Value) -> Result<serde_json::value::Value> {
+        let query: BaseAction = serde_json::from_value(query)
+            .map_err(|err| anyhow!("invalid {} state query: {:?}", self.name(), err))?;
+        let device = self
+            .get_device_by_name_or_id(&query.uuid, &query.device)
+            .await?;
+
+        Ok(serde_json::json!({
+            "name": device.name(),
+            "uuid": device.unique_id(),
+            "on": device.on(),
+            "brightness": device.brightness(),
+        }))
+    }
 }
@@ -1,5 +1,6 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use std::collections::HashMap;
 
 use crate::integrations::integration;
 
@@ -14,13 +15,13 @@ impl integration::IntegrationConfig for IntegrationConfig {
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 struct GetAction {
     url: String,
 }
 
 // mayber use #[serde(untagged)] for this?
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(tag = "action")]
 enum Actions {
     #[serde(rename = "get")]
@@ -76,4 +77,12 @@ impl integration::Integration for Integration {
             Actions::Get(get_action) => self.execute_get_request(get_action.url).await,
         }
     }
+
+    async fn describe(&self) -> integration::IntegrationDescription {
+        integration::IntegrationDescription {
+            name: self.name.to_string(),
+            actions: schemars::schema_for!(Actions),
+            entities: HashMap::new(),
+        }
+    }
 }
@@ -1,217 +1,933 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use futures_util::{future, StreamExt};
+use huehue::color::{kelvin_to_mirek, Component, MirekSchema, RGB8};
+use huehue::events::{Event, LightEvent};
 use huehue::models::device_type::DeviceType;
-use huehue::{Hue, Light};
+use huehue::models::scenes::SceneRecallAction;
+use huehue::{Hue, Light, LightUpdate};
 use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::info;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
 
 use crate::integrations::integration;
+use crate::integrations::utils::name_sync::NameSync;
+use crate::secrets;
+
+// wait before reconnecting to the event stream, eg while the bridge restarts
+const EVENT_RECONNECT_SEC: u64 = 10;
+// how often to try pairing while waiting for the link button
+const PAIR_RETRY_SEC: u64 = 2;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct IntegrationConfig {
-    pub auth: String,
+    // application key, either directly or as the name of a secret written by pairing
+    pub auth: Option<String>,
+    pub auth_secret: Option<String>,
+    // picks the bridge when several are discovered, eg 001788FFFE123456
+    pub bridge_id: Option<String>,
+    // skips discovery
+    pub address: Option<Ipv4Addr>,
 }
 
 #[async_trait]
 impl integration::IntegrationConfig for IntegrationConfig {
     async fn into_integration(&self, name: Option<String>) -> integration::IntegrationResult {
-        let auth = shellexpand::env(&self.auth)?.to_string();
-        let i = Integration::new(name.unwrap_or("hue".to_string()).as_ref(), &auth).await?;
+        let auth = match (&self.auth, &self.auth_secret) {
+            (Some(auth), _) => shellexpand::env(auth)?.to_string(),
+            (None, Some(secret)) => secrets::get(secret)?.ok_or_else(|| {
+                anyhow!(
+                    "secret {} not found, pair with the bridge to create it",
+                    secret
+                )
+            })?,
+            (None, None) => return Err(anyhow!("hue requires auth or auth_secret")),
+        };
+        let i = Integration::new(
+            name.unwrap_or("hue".to_string()).as_ref(),
+            &auth,
+            self.bridge_id.as_deref(),
+            self.address,
+        )
+        .await?;
         return Ok(i.into());
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct ToggleOrSetAction {
+// Target picks what an action or state query applies to, one of light, room, zone or entertainment
+// must be set
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct Target {
     light: Option<String>,
     room: Option<String>,
+    // zones can span rooms, eg downstairs
+    zone: Option<String>,
+    // entertainment area, changes are sent to each of its lights
+    entertainment: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct ToggleOrSetAction {
+    #[serde(flatten)]
+    target: Target,
     brightness: Option<f32>,
     rel_brightness: Option<f32>,
+    // ignored when color_temperature or rel_color_temperature is set as well
+    color: Option<ColorOption>,
+    color_temperature: Option<ColorTemperatureOption>,
+    // change in mirek, positive is warmer
+    rel_color_temperature: Option<i32>,
+    transition_ms: Option<u32>,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum AlertKind {
+    #[default]
+    Breathe,
+    // blinks a single light so it can be found
+    Identify,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct AlertAction {
+    #[serde(flatten)]
+    target: Target,
+    #[serde(default)]
+    alert: AlertKind,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct EffectAction {
+    #[serde(flatten)]
+    target: Target,
+    // eg candle or fire, no_effect stops the current effect. Rooms and zones set it on each of their
+    // lights that supports it.
+    effect: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+enum ColorOption {
+    // #rrggbb
+    Hex(String),
+    // CIE xy coordinates
+    Xy { x: f32, y: f32 },
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+enum ColorTemperatureOption {
+    Kelvin { kelvin: u32 },
+    Mirek { mirek: u32 },
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct SceneAction {
+    // the room or zone the scene belongs to
+    room: Option<String>,
+    zone: Option<String>,
+    scene: String,
+    // keep cycling through the scene's colors instead of setting them once
+    #[serde(default)]
+    dynamic_palette: bool,
+    transition_ms: Option<u32>,
 }
 
-// mayber use #[serde(untagged)] for this?
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct StateQuery {
+    #[serde(flatten)]
+    target: Target,
+}
+
+// mayber use #[serde(untagged)] for this?
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(tag = "action")]
 enum Actions {
     #[serde(rename = "toggle")]
     Toggle(ToggleOrSetAction),
     #[serde(rename = "set")]
     Set(ToggleOrSetAction),
+    #[serde(rename = "scene")]
+    Scene(SceneAction),
+    #[serde(rename = "alert")]
+    Alert(AlertAction),
+    #[serde(rename = "effect")]
+    Effect(EffectAction),
 }
 
 pub struct Integration {
     name: String,
     hue: Hue,
+    // refreshed in the background and on lookup misses, so lights can be renamed or added without a
+    // restart
+    names: RwLock<Box<Names>>,
+    name_sync: NameSync,
+    // shared with the event stream task
+    events: Arc<EventState>,
+    // the event stream task, stopped when the integration is dropped
+    event_task: Option<JoinHandle<()>>,
+}
+
+impl Drop for Integration {
+    fn drop(&mut self) {
+        if let Some(event_task) = &self.event_task {
+            event_task.abort();
+        }
+    }
+}
+
+struct EventState {
+    // light and grouped light id -> state, kept up to date by the event stream. None while the event
+    // stream isn't connected, since updates would be missed.
+    states: RwLock<Option<HashMap<String, CachedState>>>,
+    changes: broadcast::Sender<()>,
+}
+
+struct ResolvedTarget {
+    id: String,
+    name: String,
+    // grouped light of a room or zone
+    group: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct CachedState {
+    on: bool,
+    brightness: Option<f32>,
+    xy: Option<Component>,
+    mirek: Option<u32>,
+}
+
+impl CachedState {
+    fn new(light: &Light) -> CachedState {
+        CachedState {
+            on: light.on,
+            brightness: light.brightness,
+            xy: light.color.as_ref().map(|color| color.xy.clone()),
+            mirek: light
+                .temperature
+                .as_ref()
+                .and_then(|temperature| temperature.mirek),
+        }
+    }
+
+    // combine merges the states of the lights of an entertainment area, it is on when any light is
+    // on and the brightness and temperature are averaged over the lights that report them
+    fn combine(mut states: Vec<CachedState>) -> CachedState {
+        if states.len() == 1 {
+            return states.remove(0);
+        }
+
+        let average = |values: Vec<f32>| {
+            (!values.is_empty()).then(|| values.iter().sum::<f32>() / values.len() as f32)
+        };
+        CachedState {
+            on: states.iter().any(|state| state.on),
+            brightness: average(states.iter().filter_map(|state| state.brightness).collect()),
+            xy: None,
+            mirek: average(
+                states
+                    .iter()
+                    .filter_map(|state| state.mirek.map(|mirek| mirek as f32))
+                    .collect(),
+            )
+            .map(|mirek| mirek.round() as u32),
+        }
+    }
+
+    fn apply(&mut self, event: &LightEvent) {
+        if let Some(on) = event.on {
+            self.on = on;
+        }
+        if event.brightness.is_some() {
+            self.brightness = event.brightness;
+        }
+        if let Some(xy) = &event.xy {
+            self.xy = Some(xy.clone());
+        }
+        // cleared when the light switches to color mode
+        if let Some(mirek) = event.mirek {
+            self.mirek = mirek;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Names {
     light_name_to_id: HashMap<String, String>,
     room_name_to_light_group_id: HashMap<String, String>,
+    zone_name_to_light_group_id: HashMap<String, String>,
+    // keyed by room or zone name and scene name, since scene names are only unique within a room or
+    // zone
+    scene_name_to_id: HashMap<(String, String), String>,
+    // grouped light id of a room or zone -> ids of its lights
+    group_light_ids: HashMap<String, Vec<String>>,
+    entertainment_name_to_light_ids: HashMap<String, Vec<String>>,
+}
+
+type NameMap<K, V = String> = fn(&Names) -> &HashMap<K, V>;
+
+fn insert_name<K: Eq + Hash + Debug, V>(
+    map: &mut HashMap<K, V>,
+    collisions: &mut Vec<String>,
+    kind: &str,
+    name: K,
+    id: V,
+) {
+    if map.contains_key(&name) {
+        collisions.push(format!("{} {:?}", kind, name));
+    }
+    map.insert(name, id);
+}
+
+fn device_type() -> Result<DeviceType> {
+    DeviceType::new("benjamin".to_owned(), "streamdeck".to_owned())
+        .map_err(|err| anyhow!("failed to create hue device type: {:?}", err))
+}
+
+pub struct PairedBridge {
+    pub bridge_id: String,
+    pub address: Ipv4Addr,
+    pub application_key: String,
+}
+
+// pair creates an application key on the bridge, retrying until its link button is pressed or
+// timeout passes
+pub async fn pair(
+    bridge_id: Option<&str>,
+    address: Option<Ipv4Addr>,
+    timeout: Duration,
+) -> Result<PairedBridge> {
+    let address = match address {
+        Some(address) => address,
+        None => find_bridge(bridge_id).await?,
+    };
+    let mut hue = Hue::connect(address, device_type()?, bridge_id, None)
+        .await
+        .map_err(|err| anyhow!("failed to determine bridge information: {:?}", err))?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    info!(
+        bridge_id = hue.bridge().id,
+        ?address,
+        "press the link button on the hue bridge"
+    );
+    loop {
+        match hue.authorize().await {
+            Ok(()) => break,
+            // also returned while the link button hasn't been pressed
+            Err(huehue::HueError::Unauthorized) if tokio::time::Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_secs(PAIR_RETRY_SEC)).await;
+            }
+            Err(huehue::HueError::Unauthorized) => {
+                return Err(anyhow!(
+                    "link button wasn't pressed within {}s",
+                    timeout.as_secs()
+                ))
+            }
+            Err(err) => return Err(anyhow!("failed to pair with hue bridge: {}", err)),
+        }
+    }
+
+    Ok(PairedBridge {
+        bridge_id: hue.bridge().id.clone(),
+        address,
+        application_key: hue
+            .application_key()
+            .ok_or_else(|| anyhow!("hue bridge didn't return an application key"))?,
+    })
+}
+
+// find_bridge discovers the bridge with bridge_id, or the one with the lowest id when it isn't set so
+// the same bridge is picked every time
+async fn find_bridge(bridge_id: Option<&str>) -> Result<Ipv4Addr> {
+    let mut bridges = Hue::bridges(Duration::from_secs(5)).await;
+    bridges.sort_by(|a, b| a.id.cmp(&b.id));
+
+    if let Some(bridge_id) = bridge_id {
+        return bridges
+            .iter()
+            .find(|bridge| bridge.id.eq_ignore_ascii_case(bridge_id))
+            .map(|bridge| bridge.address)
+            .ok_or_else(|| anyhow!("hue bridge {} not found", bridge_id));
+    }
+
+    if bridges.len() > 1 {
+        warn!(
+            bridges = ?bridges.iter().map(|bridge| &bridge.id).collect::<Vec<_>>(),
+            "found multiple hue bridges, set bridge_id or address to pick one"
+        );
+    }
+    bridges
+        .first()
+        .map(|bridge| bridge.address)
+        .ok_or_else(|| anyhow!("getting hue bridges failed"))
+}
+
+fn grouped_light_id(group: &huehue::Room) -> Option<String> {
+    group
+        .services
+        .iter()
+        .find(|service| service.rtype == "grouped_light")
+        .map(|service| service.rid.to_string())
+}
+
+fn group_light_ids(
+    group: &huehue::Room,
+    member_light_ids: &HashMap<String, Vec<String>>,
+) -> Vec<String> {
+    group
+        .children
+        .iter()
+        .filter_map(|child| member_light_ids.get(&child.rid.to_string()))
+        .flatten()
+        .cloned()
+        .collect()
 }
 
 impl Integration {
-    pub async fn new(name: &str, application_key: &str) -> Result<Integration> {
-        let bridges = Hue::bridges(Duration::from_secs(5)).await;
-        let device_type = DeviceType::new("benjamin".to_owned(), "streamdeck".to_owned())
-            .map_err(|err| anyhow!("failed to create hue device type: {:?}", err))?;
-
-        let bridge_address = bridges
-            .first()
-            .ok_or_else(|| anyhow!("getting hue bridges failed"))?
-            .address;
-        let hue = Hue::new_with_key(bridge_address, device_type, application_key.to_string())
-            .await
-            .map_err(|err| anyhow!("failed to determine bridge information: {:?}", err))?;
+    pub async fn new(
+        name: &str,
+        application_key: &str,
+        bridge_id: Option<&str>,
+        address: Option<Ipv4Addr>,
+    ) -> Result<Integration> {
+        let device_type = device_type()?;
+
+        let bridge_address = match address {
+            Some(address) => address,
+            None => find_bridge(bridge_id).await?,
+        };
+        let hue = Hue::connect(
+            bridge_address,
+            device_type,
+            bridge_id,
+            Some(application_key.to_string()),
+        )
+        .await
+        .map_err(|err| anyhow!("failed to determine bridge information: {:?}", err))?;
+
+        if let Some(bridge_id) = bridge_id {
+            if !hue.bridge().id.eq_ignore_ascii_case(bridge_id) {
+                return Err(anyhow!(
+                    "hue bridge at {} has id {}, expected {}",
+                    bridge_address,
+                    hue.bridge().id,
+                    bridge_id
+                ));
+            }
+        }
 
         let mut hue_integration = Integration {
             name: name.to_string(),
             hue: hue,
-            light_name_to_id: HashMap::new(),
-            room_name_to_light_group_id: HashMap::new(),
+            names: RwLock::default(),
+            name_sync: NameSync::default(),
+            events: Arc::new(EventState {
+                states: RwLock::new(None),
+                changes: broadcast::channel(16).0,
+            }),
+            event_task: None,
         };
 
-        hue_integration.sync().await?;
+        let collisions = hue_integration.sync().await?;
 
-        info!(
-            lights = ?hue_integration.light_name_to_id.keys(),
-            rooms = ?hue_integration.room_name_to_light_group_id.keys(),
-            address = ?bridge_address,
-            "Connected to hue bridge",
-        );
+        {
+            let names = hue_integration.names.read().await;
+            info!(
+                lights = ?names.light_name_to_id.keys(),
+                rooms = ?names.room_name_to_light_group_id.keys(),
+                zones = ?names.zone_name_to_light_group_id.keys(),
+                entertainment_areas = ?names.entertainment_name_to_light_ids.keys(),
+                scenes = ?names.scene_name_to_id.keys(),
+                address = ?bridge_address,
+                bridge_id = hue_integration.hue.bridge().id,
+                "Connected to hue bridge",
+            );
+            if !collisions.is_empty() {
+                warn!(
+                    ?collisions,
+                    "hue names are used more than once, rename them in the hue app to use them"
+                );
+            }
+        }
+
+        hue_integration.event_task = Some(tokio::spawn(watch_events(
+            hue_integration.name.clone(),
+            hue_integration.hue.clone(),
+            hue_integration.events.clone(),
+        )));
 
         return Ok(hue_integration);
     }
 
-    async fn sync(&mut self) -> Result<()> {
-        let lights = self.hue.lights().await?;
-        self.light_name_to_id.clear();
+    // sync reloads every name from the bridge, returning the names that are used more than once as
+    // only the last one can be used
+    async fn sync(&self) -> Result<Vec<String>> {
+        self.name_sync.sync(self.load_names()).await
+    }
+
+    async fn load_names(&self) -> Result<Vec<String>> {
+        let mut names = Names::default();
+        let mut collisions = Vec::new();
 
-        for light in lights {
-            self.light_name_to_id
-                .insert(light.name, light.id.to_string());
+        // rooms contain devices and zones contain lights, both are resolved to lights
+        let mut member_light_ids: HashMap<String, Vec<String>> = HashMap::new();
+        for light in self.hue.lights().await? {
+            member_light_ids
+                .entry(light.id.to_string())
+                .or_default()
+                .push(light.id.to_string());
+            if let Some(owner) = light.owner {
+                member_light_ids
+                    .entry(owner.to_string())
+                    .or_default()
+                    .push(light.id.to_string());
+            }
+            insert_name(
+                &mut names.light_name_to_id,
+                &mut collisions,
+                "light",
+                light.name,
+                light.id.to_string(),
+            );
         }
 
-        let rooms = self.hue.rooms().await?;
-        self.room_name_to_light_group_id.clear();
+        let mut group_id_to_name = HashMap::new();
+        for room in self.hue.rooms().await? {
+            group_id_to_name.insert(room.id, room.name.clone());
+            if let Some(id) = grouped_light_id(&room) {
+                names
+                    .group_light_ids
+                    .insert(id.clone(), group_light_ids(&room, &member_light_ids));
+                insert_name(
+                    &mut names.room_name_to_light_group_id,
+                    &mut collisions,
+                    "room",
+                    room.name,
+                    id,
+                );
+            }
+        }
+        for zone in self.hue.zones().await? {
+            group_id_to_name.insert(zone.id, zone.name.clone());
+            if let Some(id) = grouped_light_id(&zone) {
+                names
+                    .group_light_ids
+                    .insert(id.clone(), group_light_ids(&zone, &member_light_ids));
+                insert_name(
+                    &mut names.zone_name_to_light_group_id,
+                    &mut collisions,
+                    "zone",
+                    zone.name,
+                    id,
+                );
+            }
+        }
 
-        for room in rooms {
-            // seems to be needed to avoid some move issue, idk why
-            let room_name = &room.name;
-            for service_index in 0..room.services.len() {
-                let service = &room.services[service_index];
-                if service.rtype != "grouped_light".to_string() {
-                    continue;
-                }
-                self.room_name_to_light_group_id
-                    .insert(room_name.to_string(), service.rid.to_string());
+        for area in self.hue.entertainment_configurations().await? {
+            insert_name(
+                &mut names.entertainment_name_to_light_ids,
+                &mut collisions,
+                "entertainment area",
+                area.name,
+                area.light_ids.iter().map(|id| id.to_string()).collect(),
+            );
+        }
+
+        for scene in self.hue.scenes().await? {
+            if let Some(group_name) = group_id_to_name.get(&scene.group.rid) {
+                insert_name(
+                    &mut names.scene_name_to_id,
+                    &mut collisions,
+                    "scene",
+                    (group_name.to_string(), scene.name),
+                    scene.id.to_string(),
+                );
             }
         }
 
-        Ok(())
+        // swap the maps in at once so lookups never see a partial sync
+        **self.names.write().await = names;
+        Ok(collisions)
     }
 
-    async fn get_light_by_name(&self, name: &str) -> Result<huehue::Light> {
-        let id = match self.light_name_to_id.get(name) {
-            Some(x) => x.to_string(),
-            None => return Err(anyhow!("Light named {} not found", name)),
-        };
-
-        Ok(self.hue.light_by_id(id).await?)
+    // lookup_id finds the kind of entity named name in the map picked by map, resyncing with the
+    // bridge if it isn't known yet
+    async fn lookup_id<K: Eq + Hash + Debug, V: Clone>(
+        &self,
+        map: NameMap<K, V>,
+        kind: &str,
+        name: &K,
+    ) -> Result<Option<V>> {
+        self.name_sync
+            .lookup_or_sync(
+                &format!("{} {:?}", kind, name),
+                || async { map(&*self.names.read().await).get(name).cloned() },
+                async {
+                    info!(kind, ?name, "hue name not found, resyncing with bridge");
+                    self.load_names().await.map(|_| ())
+                },
+            )
+            .await
     }
 
-    async fn get_room_light_by_name(&self, name: &str) -> Result<huehue::Light> {
-        let id = match self.room_name_to_light_group_id.get(name) {
-            Some(x) => x.to_string(),
-            None => {
+    // resolve_target finds the id of the light, or the grouped light of the room or zone, an action
+    // or query is for
+    async fn resolve_target(&self, target: &Target) -> Result<ResolvedTarget> {
+        let (map, kind, name): (NameMap<String>, _, _) = match target {
+            Target {
+                light: Some(name), ..
+            } => (|names| &names.light_name_to_id, "Light", name),
+            Target {
+                room: Some(name), ..
+            } => (|names| &names.room_name_to_light_group_id, "Room", name),
+            Target {
+                zone: Some(name), ..
+            } => (|names| &names.zone_name_to_light_group_id, "Zone", name),
+            _ => {
                 return Err(anyhow!(
-                    "Room named {} not found or didn't have any lights",
-                    name
+                    "One of light, room, zone or entertainment options must be set"
                 ))
             }
         };
 
-        Ok(self.hue.light_group_by_id(id).await?)
+        let id = self
+            .lookup_id(map, kind, name)
+            .await?
+            .ok_or_else(|| match kind {
+                "Light" => anyhow!("Light named {} not found", name),
+                _ => anyhow!(
+                    "{} named {} not found or didn't have any lights",
+                    kind,
+                    name
+                ),
+            })?;
+        Ok(ResolvedTarget {
+            id,
+            name: name.to_string(),
+            group: target.light.is_none(),
+        })
     }
 
-    async fn toggle_light_action(
-        &self,
-        light_name: String,
-        brightness: Option<f32>,
-        rel_brightness: Option<f32>,
-    ) -> Result<()> {
-        let light = self.get_light_by_name(&light_name).await?;
+    // resolve_targets is resolve_target for every light of an entertainment area, which have to be
+    // changed one by one
+    async fn resolve_targets(&self, target: &Target) -> Result<Vec<ResolvedTarget>> {
+        let name = match target {
+            Target {
+                light: None,
+                room: None,
+                zone: None,
+                entertainment: Some(name),
+            } => name,
+            _ => return Ok(vec![self.resolve_target(target).await?]),
+        };
 
-        return self.toggle_light(light, brightness, rel_brightness).await;
+        let ids = self
+            .lookup_id(
+                |names| &names.entertainment_name_to_light_ids,
+                "Entertainment area",
+                name,
+            )
+            .await?
+            .filter(|ids| !ids.is_empty())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Entertainment area named {} not found or didn't have any lights",
+                    name
+                )
+            })?;
+        Ok(ids
+            .into_iter()
+            .map(|id| ResolvedTarget {
+                id,
+                name: name.to_string(),
+                group: false,
+            })
+            .collect())
     }
 
-    async fn set_light_action(
-        &self,
-        light_name: String,
-        brightness: Option<f32>,
-        rel_brightness: Option<f32>,
-    ) -> Result<()> {
-        let light = self.get_light_by_name(&light_name).await?;
+    // group_mirek is the average color temperature of the lights in a room or zone, or the middle of
+    // the supported range when none of them are showing a color temperature
+    async fn group_mirek(&self, group_id: &str) -> Result<u32> {
+        let member_ids = self
+            .names
+            .read()
+            .await
+            .group_light_ids
+            .get(group_id)
+            .cloned()
+            .unwrap_or_default();
+
+        let mireks: Vec<u32> = self
+            .hue
+            .lights()
+            .await?
+            .iter()
+            .filter(|light| member_ids.contains(&light.id.to_string()))
+            .filter_map(|light| light.temperature.as_ref())
+            .filter(|temperature| temperature.mirek_valid)
+            .filter_map(|temperature| temperature.mirek)
+            .collect();
 
-        return self.set_light(light, brightness, rel_brightness).await;
+        if mireks.is_empty() {
+            let schema = MirekSchema::default();
+            return Ok((schema.mirek_minimum + schema.mirek_maximum) / 2);
+        }
+        Ok(mireks.iter().sum::<u32>() / mireks.len() as u32)
     }
 
-    async fn toggle_light(
-        &self,
-        mut light: Light,
-        brightness: Option<f32>,
-        rel_brightness: Option<f32>,
-    ) -> Result<()> {
-        // turn light off if it is on, otherwise turn it on then set the brightness
-        if light.on {
-            return Ok(light.switch(false).await?);
+    async fn get_light(&self, target: &ResolvedTarget) -> Result<huehue::Light> {
+        if target.group {
+            Ok(self.hue.light_group_by_id(target.id.to_string()).await?)
+        } else {
+            Ok(self.hue.light_by_id(target.id.to_string()).await?)
         }
+    }
 
-        return self.set_light(light, brightness, rel_brightness).await;
+    async fn get_target_lights(&self, target: &Target) -> Result<Vec<huehue::Light>> {
+        let targets = self.resolve_targets(target).await?;
+        future::try_join_all(targets.iter().map(|target| self.get_light(target))).await
     }
 
-    async fn set_light(
-        &self,
-        mut light: Light,
-        brightness_option: Option<f32>,
-        rel_brightness_option: Option<f32>,
-    ) -> Result<()> {
-        let light_state = crate::utils::light_utils::calc_light_state(
-            light.brightness,
-            brightness_option,
-            rel_brightness_option,
-        );
+    // target_state is the state kept up to date by the event stream, fetched from the bridge the
+    // first time it is asked for
+    async fn target_state(&self, target: &ResolvedTarget) -> Result<CachedState> {
+        let cached = self
+            .events
+            .states
+            .read()
+            .await
+            .as_ref()
+            .and_then(|states| states.get(&target.id).cloned());
+        if let Some(state) = cached {
+            return Ok(state);
+        }
+
+        let state = CachedState::new(&self.get_light(target).await?);
+        if let Some(states) = self.events.states.write().await.as_mut() {
+            states.insert(target.id.to_string(), state.clone());
+        }
+        Ok(state)
+    }
 
-        light.switch(light_state.on).await?;
+    async fn recall_scene(&self, action: &SceneAction) -> Result<()> {
+        let group_name = action
+            .room
+            .as_ref()
+            .or(action.zone.as_ref())
+            .ok_or_else(|| anyhow!("Either room or zone options must be set"))?;
+        let id = self
+            .lookup_id(
+                |names| &names.scene_name_to_id,
+                "Scene",
+                &(group_name.to_string(), action.scene.to_string()),
+            )
+            .await?
+            .ok_or_else(|| anyhow!("Scene {} not found in {}", action.scene, group_name))?;
 
-        match light_state.brightness {
-            Some(brightness) => light.dimm(brightness).await?,
-            None => (),
+        let recall_action = if action.dynamic_palette {
+            SceneRecallAction::DynamicPalette
+        } else {
+            SceneRecallAction::Active
         };
+        Ok(self
+            .hue
+            .recall_scene(id, recall_action, action.transition_ms)
+            .await?)
+    }
 
-        Ok(())
+    // effect_lights are the lights to set an effect on. Only single lights have effects, so rooms and
+    // zones use those of their lights that support the effect.
+    async fn effect_lights(&self, action: &EffectAction) -> Result<Vec<Light>> {
+        let targets = self.resolve_targets(&action.target).await?;
+        let group = match targets.as_slice() {
+            [target] if target.group => target,
+            _ => {
+                let lights =
+                    future::try_join_all(targets.iter().map(|target| self.get_light(target)))
+                        .await?;
+                for light in &lights {
+                    check_effect(light, &action.effect)?;
+                }
+                return Ok(lights);
+            }
+        };
+
+        let light_ids = self
+            .names
+            .read()
+            .await
+            .group_light_ids
+            .get(&group.id)
+            .cloned()
+            .unwrap_or_default();
+        let lights: Vec<Light> =
+            future::try_join_all(light_ids.into_iter().map(|id| self.hue.light_by_id(id)))
+                .await?
+                .into_iter()
+                .filter(|light| check_effect(light, &action.effect).is_ok())
+                .collect();
+        if lights.is_empty() {
+            return Err(anyhow!(
+                "none of the lights in {} support effect {}",
+                group.name,
+                action.effect
+            ));
+        }
+        Ok(lights)
     }
 
-    async fn toggle_room_action(
-        &self,
-        room_name: String,
-        brightness: Option<f32>,
-        rel_brightness: Option<f32>,
-    ) -> Result<()> {
-        let light = self.get_room_light_by_name(&room_name).await?;
+    async fn set_effect(&self, mut light: Light, effect: &str) -> Result<()> {
+        // effects only show while the light is on
+        if !light.on && effect != "no_effect" {
+            light.switch(true).await?;
+        }
+        Ok(light.set_effect(effect).await?)
+    }
 
-        return self.toggle_light(light, brightness, rel_brightness).await;
+    // toggle_lights turns the lights off if any of them is on, so an entertainment area toggles as
+    // one, otherwise it turns them on then sets the brightness
+    async fn toggle_lights(&self, lights: Vec<Light>, action: &ToggleOrSetAction) -> Result<()> {
+        if lights.iter().any(|light| light.on) {
+            future::try_join_all(lights.into_iter().map(|mut light| async move {
+                light.transition_ms = action.transition_ms;
+                light.switch(false).await
+            }))
+            .await?;
+            return Ok(());
+        }
+
+        future::try_join_all(
+            lights
+                .into_iter()
+                .map(|light| self.set_light(light, action)),
+        )
+        .await?;
+        Ok(())
     }
 
-    async fn set_room_action(
-        &self,
-        room_name: String,
-        brightness: Option<f32>,
-        rel_brightness: Option<f32>,
-    ) -> Result<()> {
-        let light = self.get_room_light_by_name(&room_name).await?;
+    async fn set_light(&self, mut light: Light, action: &ToggleOrSetAction) -> Result<()> {
+        light.transition_ms = action.transition_ms;
+        let changes_color = action.color.is_some()
+            || action.color_temperature.is_some()
+            || action.rel_color_temperature.is_some();
+
+        let light_state =
+            if changes_color && action.brightness.is_none() && action.rel_brightness.is_none() {
+                // only changing the color keeps the current brightness
+                crate::utils::light_utils::LightState {
+                    on: true,
+                    brightness: None,
+                }
+            } else {
+                crate::utils::light_utils::calc_light_state(
+                    light.brightness,
+                    action.brightness,
+                    action.rel_brightness,
+                )
+            };
 
-        return self.set_light(light, brightness, rel_brightness).await;
+        if !light_state.on {
+            return Ok(light
+                .update(LightUpdate {
+                    on: Some(false),
+                    ..Default::default()
+                })
+                .await?);
+        }
+
+        let color = match &action.color {
+            Some(ColorOption::Hex(hex)) => {
+                let rgb = RGB8::from_hex(hex)
+                    .ok_or_else(|| anyhow!("invalid color {}, expected #rrggbb", hex))?;
+                Some(light.xy_from_rgb(rgb)?)
+            }
+            Some(ColorOption::Xy { x, y }) => Some(
+                Component::new(*x, *y)
+                    .ok_or_else(|| anyhow!("invalid color x: {}, y: {}", x, y))?,
+            ),
+            None => None,
+        };
+        // grouped lights don't report a temperature, so relative changes start from their lights
+        let current_mirek = match light.temperature.as_ref().and_then(|t| t.mirek) {
+            None if light.is_group() && action.rel_color_temperature.is_some() => {
+                Some(self.group_mirek(&light.id.to_string()).await?)
+            }
+            mirek => mirek,
+        };
+        let mirek = target_mirek(&light.name, current_mirek, action)?;
+        // lights can't take both, the color temperature wins like it did when they were set one
+        // after the other
+        let color = color.filter(|_| mirek.is_none());
+
+        // a single request so brightness and color change together
+        light
+            .update(LightUpdate {
+                on: Some(true),
+                brightness: light_state.brightness,
+                color,
+                mirek,
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
     }
 }
 
+fn check_effect(light: &Light, effect: &str) -> Result<()> {
+    let effects = light
+        .effects
+        .as_ref()
+        .ok_or_else(|| anyhow!("{} doesn't support effects", light.name))?;
+    if !effects.iter().any(|e| e == effect) {
+        return Err(anyhow!(
+            "{} doesn't support effect {}, supported effects: {}",
+            light.name,
+            effect,
+            effects.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+// target_mirek is the color temperature to set, if any. rel_color_temperature is applied on top of
+// color_temperature when both are set, otherwise on top of the light's current temperature.
+fn target_mirek(
+    name: &str,
+    current_mirek: Option<u32>,
+    action: &ToggleOrSetAction,
+) -> Result<Option<u32>> {
+    let mirek = action
+        .color_temperature
+        .as_ref()
+        .map(|temperature| match temperature {
+            ColorTemperatureOption::Kelvin { kelvin } => kelvin_to_mirek(*kelvin),
+            ColorTemperatureOption::Mirek { mirek } => *mirek,
+        });
+
+    let rel_mirek = match action.rel_color_temperature {
+        Some(rel_mirek) => rel_mirek,
+        None => return Ok(mirek),
+    };
+    let current = mirek.or(current_mirek).ok_or_else(|| {
+        anyhow!(
+            "{} doesn't report its color temperature, rel_color_temperature needs it",
+            name
+        )
+    })?;
+
+    Ok(Some((current as i64 + rel_mirek as i64).max(0) as u32))
+}
+
 #[async_trait]
 impl integration::Integration for Integration {
     fn name(&self) -> &str {
@@ -233,49 +949,218 @@ impl integration::Integration for Integration {
 
         match options {
             Actions::Toggle(toggle_action) => {
-                if let Some(light_name) = toggle_action.light {
-                    return Ok(self
-                        .toggle_light_action(
-                            light_name,
-                            toggle_action.brightness,
-                            toggle_action.rel_brightness,
-                        )
-                        .await?);
-                }
-                if let Some(room_name) = toggle_action.room {
-                    return Ok(self
-                        .toggle_room_action(
-                            room_name,
-                            toggle_action.brightness,
-                            toggle_action.rel_brightness,
-                        )
-                        .await?);
-                }
-
-                return Err(anyhow!("Either light or room options must be set"));
+                let lights = self.get_target_lights(&toggle_action.target).await?;
+                self.toggle_lights(lights, &toggle_action).await
             }
             Actions::Set(set_action) => {
-                if let Some(light_name) = set_action.light {
-                    return Ok(self
-                        .set_light_action(
-                            light_name,
-                            set_action.brightness,
-                            set_action.rel_brightness,
-                        )
-                        .await?);
-                }
-                if let Some(room_name) = set_action.room {
-                    return Ok(self
-                        .set_room_action(
-                            room_name,
-                            set_action.brightness,
-                            set_action.rel_brightness,
-                        )
-                        .await?);
-                }
+                let lights = self.get_target_lights(&set_action.target).await?;
+                future::try_join_all(
+                    lights
+                        .into_iter()
+                        .map(|light| self.set_light(light, &set_action)),
+                )
+                .await
+                .map(|_| ())
+            }
+            Actions::Scene(scene_action) => self.recall_scene(&scene_action).await,
+            Actions::Alert(alert_action) => {
+                let lights = self.get_target_lights(&alert_action.target).await?;
+                future::try_join_all(lights.iter().map(|light| async {
+                    match alert_action.alert {
+                        AlertKind::Breathe => light.alert().await,
+                        AlertKind::Identify => light.identify().await,
+                    }
+                }))
+                .await?;
+                Ok(())
+            }
+            Actions::Effect(effect_action) => {
+                let lights = self.effect_lights(&effect_action).await?;
+                future::try_join_all(
+                    lights
+                        .into_iter()
+                        .map(|light| self.set_effect(light, &effect_action.effect)),
+                )
+                .await
+                .map(|_| ())
+            }
+        }
+    }
+
+    async fn describe(&self) -> integration::IntegrationDescription {
+        let names = self.names.read().await;
+        // scenes are listed as room or zone/scene
+        let scenes: Vec<String> = names
+            .scene_name_to_id
+            .keys()
+            .map(|(group, scene)| format!("{}/{}", group, scene))
+            .collect();
+        integration::IntegrationDescription {
+            name: self.name.to_string(),
+            actions: schemars::schema_for!(Actions),
+            entities: HashMap::from([
+                (
+                    "lights".to_string(),
+                    integration::sorted_names(names.light_name_to_id.keys()),
+                ),
+                (
+                    "rooms".to_string(),
+                    integration::sorted_names(names.room_name_to_light_group_id.keys()),
+                ),
+                (
+                    "zones".to_string(),
+                    integration::sorted_names(names.zone_name_to_light_group_id.keys()),
+                ),
+                (
+                    "entertainment_areas".to_string(),
+                    integration::sorted_names(names.entertainment_name_to_light_ids.keys()),
+                ),
+                (
+                    "scenes".to_string(),
+                    integration::sorted_names(scenes.iter()),
+                ),
+            ]),
+        }
+    }
+
+    async fn refresh(&self) -> Result<()> {
+        self.sync().await.map(|_| ())
+    }
+
+    async fn get_state(&self, query: serde_json::value::Value) -> Result<serde_json::value::Value> {
+        let query: StateQuery = serde_json::from_value(query)
+            .map_err(|err| anyhow!("invalid {} state query: {:?}", self.name(), err))?;
+
+        let targets = self.resolve_targets(&query.target).await?;
+        let states =
+            future::try_join_all(targets.iter().map(|target| self.target_state(target))).await?;
 
-                return Err(anyhow!("Either light or room options must be set"));
+        let mut state = serde_json::to_value(CachedState::combine(states))?;
+        state["name"] = serde_json::Value::String(targets[0].name.to_string());
+        Ok(state)
+    }
+
+    fn state_changes(&self) -> Option<broadcast::Receiver<()>> {
+        Some(self.events.changes.subscribe())
+    }
+}
+
+// watch_events keeps states up to date from the bridge's event stream and reports every change,
+// reconnecting whenever the stream ends
+async fn watch_events(name: String, hue: Hue, events_state: Arc<EventState>) {
+    let states = &events_state.states;
+    loop {
+        match hue.events().await {
+            Ok(events) => {
+                info!(integration = name, "subscribed to hue events");
+                // start from scratch, anything cached before may have missed updates
+                *states.write().await = Some(HashMap::new());
+
+                let mut events = Box::pin(events);
+                while let Some(event) = events.next().await {
+                    match event {
+                        Ok(Event::Light(event)) => {
+                            if let Some(state) = states
+                                .write()
+                                .await
+                                .as_mut()
+                                .and_then(|states| states.get_mut(&event.id.to_string()))
+                            {
+                                state.apply(&event);
+                            }
+                        }
+                        Ok(Event::Scene(_)) => (),
+                        Err(err) => {
+                            warn!(error = ?err, integration = name, "invalid hue event");
+                            continue;
+                        }
+                    }
+                    // no receivers just means nothing is interested yet
+                    let _ = events_state.changes.send(());
+                }
+                warn!(integration = name, "hue event stream closed");
             }
+            Err(err) => {
+                warn!(error = ?err, integration = name, "failed to subscribe to hue events")
+            }
+        }
+
+        *states.write().await = None;
+        tokio::time::sleep(Duration::from_secs(EVENT_RECONNECT_SEC)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(options: serde_json::Value) -> ToggleOrSetAction {
+        serde_json::from_value(options).unwrap()
+    }
+
+    #[test]
+    fn rel_color_temperature_starts_from_current() {
+        let rel = action(serde_json::json!({"room": "Office", "rel_color_temperature": 50}));
+        assert_eq!(target_mirek("Office", Some(300), &rel).unwrap(), Some(350));
+        assert!(target_mirek("Office", None, &rel).is_err());
+
+        let both = action(serde_json::json!({
+            "room": "Office",
+            "color_temperature": {"mirek": 200},
+            "rel_color_temperature": -20,
+        }));
+        assert_eq!(target_mirek("Office", Some(300), &both).unwrap(), Some(180));
+    }
+
+    #[test]
+    fn group_lights_resolve_devices_and_lights() {
+        let device = "7e0c4b5a-1d5e-4a5e-9c8b-2a0f6d5e3c1b";
+        let light = "4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e";
+        let member_light_ids = HashMap::from([
+            (device.to_string(), vec!["device light".to_string()]),
+            (light.to_string(), vec!["zone light".to_string()]),
+        ]);
+        let group = huehue::Room {
+            id: serde_json::from_value(serde_json::json!("d1b0e6f4-3a2c-4e5b-9f8a-7c6d5e4f3a2b"))
+                .unwrap(),
+            name: "Office".to_string(),
+            services: Vec::new(),
+            children: serde_json::from_value(serde_json::json!([
+                {"rid": device, "rtype": "device"},
+                {"rid": light, "rtype": "light"},
+                {"rid": "9a1c2b3d-4e5f-4a6b-8c7d-0e1f2a3b4c5d", "rtype": "device"},
+            ]))
+            .unwrap(),
         };
+
+        assert_eq!(
+            group_light_ids(&group, &member_light_ids),
+            vec!["device light".to_string(), "zone light".to_string()]
+        );
+    }
+
+    #[test]
+    fn color_mode_clears_cached_mirek() {
+        let mut state = CachedState {
+            on: true,
+            brightness: Some(50.0),
+            xy: None,
+            mirek: Some(300),
+        };
+        let event = |mirek| LightEvent {
+            id: serde_json::from_value(serde_json::json!("4f6a5bd9-2b3c-4b4c-a3f4-0b8b8b1f9d2e"))
+                .unwrap(),
+            resource: huehue::light::LightResource::Light,
+            on: None,
+            brightness: Some(60.0),
+            xy: None,
+            mirek,
+        };
+
+        state.apply(&event(None));
+        assert_eq!(state.mirek, Some(300));
+        state.apply(&event(Some(None)));
+        assert_eq!(state.mirek, None);
+        assert_eq!(state.brightness, Some(60.0));
     }
 }
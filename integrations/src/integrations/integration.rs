@@ -1,11 +1,23 @@
 use crate::integrations::IntegrationEnum;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use enum_dispatch::enum_dispatch;
+use std::collections::HashMap;
 
 pub type IntegrationResult = Result<IntegrationEnum>;
 
+// IntegrationDescription describes what an integration can do, used by editors for autocompletion
+#[derive(Debug, serde::Serialize)]
+pub struct IntegrationDescription {
+    pub name: String,
+    // json schema of the action options, actions are prefixed with the integration name in the
+    // config, eg hue::toggle
+    pub actions: schemars::schema::RootSchema,
+    // entity kind -> known names, eg lights -> [Desk, Kitchen]
+    pub entities: HashMap<String, Vec<String>>,
+}
+
 // IntegrationConfig is implemented by the integration, to convert some configuration (on the struct) to an integration in the ingegration enum
 #[async_trait]
 pub trait IntegrationConfig {
@@ -19,6 +31,25 @@ pub trait Integration {
     fn name(&self) -> &str;
     async fn execute_action(&self, action: String, options: serde_json::value::Value)
         -> Result<()>;
+    async fn describe(&self) -> IntegrationDescription;
+    // refresh reloads anything cached from the remote service, eg entity names, it is called
+    // periodically by the server
+    async fn refresh(&self) -> Result<()> {
+        Ok(())
+    }
+    // get_state returns the current state for the query as json, the shape of both is integration
+    // specific
+    async fn get_state(
+        &self,
+        _query: serde_json::value::Value,
+    ) -> Result<serde_json::value::Value> {
+        Err(anyhow!("{} does not support state queries", self.name()))
+    }
+    // state_changes notifies about state changes pushed by the remote service, integrations that
+    // can only be polled return None
+    fn state_changes(&self) -> Option<tokio::sync::broadcast::Receiver<()>> {
+        None
+    }
 }
 
 // IntoIntegration is a helper trait for converting an integration into an integration result
@@ -28,6 +59,7 @@ pub trait Integration {
 #[enum_dispatch]
 pub trait IntoIntegration {
     async fn into_integration(&self) -> IntegrationResult;
+    fn configured_name(&self) -> Option<&str>;
 }
 
 // IntegrationConfiguration implements IntoIntegration, by calling IntegrationConfig, with the embeded name
@@ -47,4 +79,15 @@ where
     async fn into_integration(&self) -> IntegrationResult {
         return self.options.into_integration(self.name.clone()).await;
     }
+
+    fn configured_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+// sorted_names lists entity names in a stable order for IntegrationDescription
+pub fn sorted_names<'a>(names: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut names: Vec<String> = names.cloned().collect();
+    names.sort();
+    names
 }
@@ -1,6 +1,7 @@
 use crate::integrations::{airplay, homebridge, http, hue};
 use crate::integrations::{
-    Integration, IntegrationConfiguration, IntegrationResult, IntoIntegration,
+    Integration, IntegrationConfiguration, IntegrationDescription, IntegrationResult,
+    IntoIntegration,
 };
 
 use anyhow::Result;
@@ -25,6 +26,16 @@ pub enum IntegrationsConfigurationEnum {
     Http(IntegrationConfiguration<http::IntegrationConfig>),
 }
 
+impl IntegrationsConfigurationEnum {
+    // name is what the integration will be registered as, known before it is set up. Integrations
+    // default to their type as name.
+    pub fn name(&self) -> String {
+        self.configured_name()
+            .map(str::to_string)
+            .unwrap_or_else(|| self.to_string())
+    }
+}
+
 impl std::fmt::Display for IntegrationsConfigurationEnum {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
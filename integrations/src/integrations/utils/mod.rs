@@ -1 +1,2 @@
 pub(crate) mod light_utils;
+pub(crate) mod name_sync;
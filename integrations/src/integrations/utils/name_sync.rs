@@ -0,0 +1,149 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// lookup misses resync at most this often, so a misspelled name that is polled doesn't keep the
+// remote service busy
+const MIN_MISS_SYNC_SEC: u64 = 30;
+
+// NameSync guards the resyncs integrations run when a name isn't known yet. Only one sync runs at a
+// time, and names that are still missing after a sync are remembered until the next one.
+#[derive(Default)]
+pub struct NameSync {
+    state: Mutex<SyncState>,
+}
+
+#[derive(Default)]
+struct SyncState {
+    last_sync: Option<Instant>,
+    misses: HashSet<String>,
+}
+
+impl NameSync {
+    // sync runs a full sync, waiting for one that is already running to finish first
+    pub async fn sync<T, S>(&self, sync: S) -> Result<T>
+    where
+        S: Future<Output = Result<T>>,
+    {
+        let mut state = self.state.lock().await;
+        let result = sync.await?;
+        *state = SyncState {
+            last_sync: Some(Instant::now()),
+            misses: HashSet::new(),
+        };
+        Ok(result)
+    }
+
+    // lookup_or_sync returns what lookup finds for key, running sync and looking again on a miss
+    // unless key is a known miss or the last sync was too recent
+    pub async fn lookup_or_sync<T, L, LF, S>(
+        &self,
+        key: &str,
+        lookup: L,
+        sync: S,
+    ) -> Result<Option<T>>
+    where
+        L: Fn() -> LF,
+        LF: Future<Output = Option<T>>,
+        S: Future<Output = Result<()>>,
+    {
+        if let Some(found) = lookup().await {
+            return Ok(Some(found));
+        }
+
+        let mut state = self.state.lock().await;
+        // a sync may have finished while waiting for the lock
+        if let Some(found) = lookup().await {
+            return Ok(Some(found));
+        }
+        let recently_synced = state
+            .last_sync
+            .is_some_and(|last_sync| last_sync.elapsed() < Duration::from_secs(MIN_MISS_SYNC_SEC));
+        if state.misses.contains(key) || recently_synced {
+            state.misses.insert(key.to_string());
+            return Ok(None);
+        }
+
+        sync.await?;
+        state.last_sync = Some(Instant::now());
+        state.misses.clear();
+
+        let found = lookup().await;
+        if found.is_none() {
+            state.misses.insert(key.to_string());
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::RwLock;
+
+    struct Names {
+        names: RwLock<HashMap<String, String>>,
+        remote: HashMap<String, String>,
+        syncs: AtomicUsize,
+        name_sync: NameSync,
+    }
+
+    impl Names {
+        fn new(remote: &[(&str, &str)]) -> Names {
+            Names {
+                names: RwLock::default(),
+                remote: remote
+                    .iter()
+                    .map(|(name, id)| (name.to_string(), id.to_string()))
+                    .collect(),
+                syncs: AtomicUsize::new(0),
+                name_sync: NameSync::default(),
+            }
+        }
+
+        async fn load(&self) -> Result<()> {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.syncs.fetch_add(1, Ordering::SeqCst);
+            *self.names.write().await = self.remote.clone();
+            Ok(())
+        }
+
+        async fn lookup(&self, name: &str) -> Option<String> {
+            self.name_sync
+                .lookup_or_sync(
+                    name,
+                    || async { self.names.read().await.get(name).cloned() },
+                    self.load(),
+                )
+                .await
+                .unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_sync_once() {
+        let names = Names::new(&[("Desk", "1"), ("Kitchen", "2")]);
+
+        let (desk, kitchen) = tokio::join!(names.lookup("Desk"), names.lookup("Kitchen"));
+        assert_eq!(desk.as_deref(), Some("1"));
+        assert_eq!(kitchen.as_deref(), Some("2"));
+        assert_eq!(names.syncs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn misses_are_cached_until_the_next_sync() {
+        let names = Names::new(&[("Desk", "1")]);
+
+        assert_eq!(names.lookup("Dsk").await, None);
+        assert_eq!(names.lookup("Dsk").await, None);
+        assert_eq!(names.lookup("Kitchn").await, None);
+        assert_eq!(names.syncs.load(Ordering::SeqCst), 1);
+
+        names.name_sync.sync(names.load()).await.unwrap();
+        assert!(names.name_sync.state.lock().await.misses.is_empty());
+    }
+}
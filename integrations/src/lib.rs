@@ -1,4 +1,5 @@
 pub mod homebridge;
 pub mod integrations;
+pub mod secrets;
 
 pub use integrations::*;
@@ -0,0 +1,46 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// secrets such as hue application keys are kept in their own file, so the config can be shared
+const SECRETS_FILE_VAR: &str = "STREAM_DECK_CONTROLLER_SECRETS";
+const DEFAULT_SECRETS_FILE: &str = "./secrets.json";
+
+// serializes writes, since each one rewrites the whole file
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+fn path() -> PathBuf {
+    PathBuf::from(std::env::var(SECRETS_FILE_VAR).unwrap_or(DEFAULT_SECRETS_FILE.to_string()))
+}
+
+fn read_all(path: &PathBuf) -> Result<HashMap<String, String>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    serde_json::from_str(&std::fs::read_to_string(path)?)
+        .map_err(|err| anyhow!("invalid secrets file {:?}: {}", path, err))
+}
+
+pub fn get(name: &str) -> Result<Option<String>> {
+    Ok(read_all(&path())?.remove(name))
+}
+
+pub fn set(name: &str, value: &str) -> Result<()> {
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let path = path();
+    let mut secrets = read_all(&path)?;
+    secrets.insert(name.to_string(), value.to_string());
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // only readable by the user running the server
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    std::io::Write::write_all(
+        &mut options.open(&path)?,
+        serde_json::to_string_pretty(&secrets)?.as_bytes(),
+    )?;
+    Ok(())
+}
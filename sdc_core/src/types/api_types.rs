@@ -3,7 +3,7 @@ use tokio::sync::oneshot;
 
 #[derive(Debug)]
 pub struct ExecuteActionReq {
-    pub tx: oneshot::Sender<String>,
+    pub tx: oneshot::Sender<anyhow::Result<()>>,
     pub actions: Actions,
     pub requestor_uuid: Option<uuid::Uuid>,
 }
@@ -17,21 +17,198 @@ pub struct ProfileButtonPressed {
 pub type Profiles = Vec<Profile>;
 pub type Actions = Vec<Action>;
 
+// the variant is picked by the key that is present, checked in the order action, parallel, if and
+// delay_ms, so options of an integration action never turn it into another kind of action
+#[derive(Debug, serde::Serialize, Clone)]
+#[serde(untagged)]
+pub enum Action {
+    Parallel(ParallelAction),
+    Delay(DelayAction),
+    Conditional(ConditionalAction),
+    Integration(IntegrationAction),
+}
+
+impl<'de> serde::Deserialize<'de> for Action {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let has_key = |key| value.get(key).is_some();
+        let action = if has_key("action") {
+            serde_json::from_value(value).map(Action::Integration)
+        } else if has_key("parallel") {
+            serde_json::from_value(value).map(Action::Parallel)
+        } else if has_key("if") {
+            serde_json::from_value(value).map(Action::Conditional)
+        } else if has_key("delay_ms") {
+            serde_json::from_value(value).map(Action::Delay)
+        } else {
+            return Err(D::Error::custom(
+                "action must have one of action, parallel, if or delay_ms",
+            ));
+        };
+        action.map_err(D::Error::custom)
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
-pub struct Action {
+pub struct IntegrationAction {
     pub action: String,
     #[serde(flatten)]
+    pub modifiers: ActionModifiers,
+    #[serde(flatten)]
     pub options: serde_json::value::Value,
 }
 
+// ParallelAction runs all of its actions at the same time and waits for them to finish
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct ParallelAction {
+    pub parallel: Actions,
+    #[serde(flatten)]
+    pub modifiers: ActionModifiers,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DelayAction {
+    pub delay_ms: u64,
+}
+
+// ConditionalAction runs then when the condition holds and else otherwise
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConditionalAction {
+    #[serde(rename = "if")]
+    pub condition: types::Condition,
+    pub then: Actions,
+    #[serde(rename = "else", default, skip_serializing_if = "Vec::is_empty")]
+    pub otherwise: Actions,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct ActionModifiers {
+    // log the error and keep going with the next action instead of aborting
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub continue_on_error: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Profile {
     pub name: String,
+    // image sliced across all keys of the deck, buttons are drawn over their tile
+    pub background: Option<String>,
     pub buttons: Vec<ProfileButton>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct ProfileButton {
-    pub states: Option<Vec<types::SetButtonUI>>,
+    pub states: Option<Vec<ButtonState>>,
+    // run on every press, for cycling buttons the actions of the current state are run first
+    #[serde(default)]
     pub actions: Actions,
 }
+
+impl ProfileButton {
+    // a button cycles through its states when they carry their own actions, each press runs the
+    // current state's actions and advances to the next state
+    pub fn is_cycling(&self) -> bool {
+        self.states
+            .as_ref()
+            .is_some_and(|states| states.iter().any(|state| !state.actions.is_empty()))
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ButtonState {
+    #[serde(flatten)]
+    pub ui: types::SetButtonUI,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Actions,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: serde_json::Value) -> Action {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn integration_action_keeps_its_options() {
+        let action = parse(serde_json::json!({
+            "action": "hue::set_light",
+            "delay_ms": 500,
+            "continue_on_error": true,
+        }));
+        match action {
+            Action::Integration(action) => {
+                assert_eq!(action.action, "hue::set_light");
+                assert!(action.modifiers.continue_on_error);
+                assert_eq!(action.options["delay_ms"], 500);
+            }
+            action => panic!("parsed as {:?}", action),
+        }
+    }
+
+    #[test]
+    fn parallel_action() {
+        let action = parse(serde_json::json!({
+            "parallel": [{"action": "hue::toggle"}, {"delay_ms": 10}],
+            "timeout_ms": 100,
+        }));
+        match action {
+            Action::Parallel(action) => {
+                assert_eq!(action.parallel.len(), 2);
+                assert!(matches!(action.parallel[1], Action::Delay(_)));
+                assert_eq!(action.modifiers.timeout_ms, Some(100));
+            }
+            action => panic!("parsed as {:?}", action),
+        }
+    }
+
+    #[test]
+    fn conditional_action() {
+        let action = parse(serde_json::json!({
+            "if": {"time": {"after": "sunset"}},
+            "then": [{"action": "hue::toggle"}],
+        }));
+        match action {
+            Action::Conditional(action) => {
+                assert!(matches!(action.condition, types::Condition::Time(_)));
+                assert_eq!(action.then.len(), 1);
+                assert!(action.otherwise.is_empty());
+            }
+            action => panic!("parsed as {:?}", action),
+        }
+    }
+
+    #[test]
+    fn delay_action() {
+        let action = parse(serde_json::json!({"delay_ms": 250}));
+        assert!(matches!(
+            action,
+            Action::Delay(DelayAction { delay_ms: 250 })
+        ));
+    }
+
+    #[test]
+    fn delay_action_rejects_unknown_fields() {
+        let result = serde_json::from_value::<Action>(serde_json::json!({
+            "delay_ms": 250,
+            "acton": "hue::toggle",
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unknown_action_is_rejected() {
+        let result = serde_json::from_value::<Action>(serde_json::json!({"then": []}));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,46 @@
+// Conditions are evaluated by the server when a conditional action runs
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    State(StateCondition),
+    Time(TimeCondition),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+// StateCondition queries an integration and compares (part of) the returned state. Without a
+// comparison the value is checked for truthiness.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct StateCondition {
+    pub integration: String,
+    // json pointer into the state, eg /device_state
+    pub pointer: Option<String>,
+    #[serde(flatten)]
+    pub comparison: Comparison,
+    // everything else is passed to the integration as the state query
+    #[serde(flatten)]
+    pub query: serde_json::value::Value,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct Comparison {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equals: Option<serde_json::value::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_equals: Option<serde_json::value::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub greater_than: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub less_than: Option<f64>,
+}
+
+// TimeCondition matches the local time of the server, times are HH:MM or sunrise/sunset with an
+// optional offset in minutes, eg sunset-30. A range where after is later than before wraps around
+// midnight.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct TimeCondition {
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
@@ -0,0 +1,93 @@
+// Layers are drawn bottom to top by the server and flattened into a single key image
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    Color {
+        color: String,
+    },
+    Gradient {
+        from: String,
+        to: String,
+        #[serde(default)]
+        direction: GradientDirection,
+    },
+    Image {
+        image: String,
+    },
+    Badge {
+        color: String,
+        #[serde(default)]
+        position: BadgePosition,
+        // diameter in pixels of the rendered key image
+        size: Option<u32>,
+    },
+    Progress {
+        value: LayerValue,
+        // value that represents a full bar, defaults to 100 to match brightness
+        max: Option<f32>,
+        color: String,
+        background: Option<String>,
+    },
+}
+
+// LayerValue is a number, a placeholder such as "{{vars.volume}}" or an integration state query. The
+// server resolves it every time the button is rendered.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(untagged)]
+pub enum LayerValue {
+    Number(f32),
+    Placeholder(String),
+    State(StateValue),
+}
+
+impl LayerValue {
+    // number returns the value once it is resolved
+    pub fn number(&self) -> Option<f32> {
+        match self {
+            LayerValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl Layer {
+    // is_bound is true when the layer depends on variables or integration state
+    pub fn is_bound(&self) -> bool {
+        match self {
+            Layer::Progress { value, .. } => value.number().is_none(),
+            _ => false,
+        }
+    }
+}
+
+// StateValue queries an integration like a state condition, eg the brightness of a hue room
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct StateValue {
+    pub integration: String,
+    // json pointer into the state, eg /brightness
+    pub pointer: Option<String>,
+    // everything else is passed to the integration as the state query
+    #[serde(flatten)]
+    pub query: serde_json::value::Value,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GradientDirection {
+    #[default]
+    Vertical,
+    Horizontal,
+    Diagonal,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BadgePosition {
+    #[default]
+    TopRight,
+    TopLeft,
+    BottomRight,
+    BottomLeft,
+}
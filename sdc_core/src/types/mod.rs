@@ -1,5 +1,9 @@
 mod api_types;
+mod condition_types;
+mod layer_types;
 mod ws_types;
 
 pub use api_types::*;
+pub use condition_types::*;
+pub use layer_types::*;
 pub use ws_types::*;
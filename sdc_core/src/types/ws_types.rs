@@ -1,10 +1,36 @@
+use crate::types;
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "camelCase")]
 pub enum WsActions {
-    ButtonPressed { profile: Option<String>, button: u8 },
-    SetButtons { buttons: Vec<SetButtonUI> },
-    SetButton { index: u8, button: SetButtonUI },
+    ButtonPressed {
+        profile: Option<String>,
+        button: u8,
+    },
+    SetButtons {
+        buttons: Vec<SetButtonUI>,
+    },
+    SetButton {
+        index: u8,
+        button: SetButtonUI,
+    },
+    // shown until the next SetButton(s), clients keep the button's previous state to redraw it
+    FlashButton {
+        index: u8,
+        button: SetButtonUI,
+    },
+    ClientInfo {
+        layout: DeckLayout,
+        // lets config such as schedules target specific decks
+        #[serde(default)]
+        name: Option<String>,
+    },
+    ActionResult {
+        button: u8,
+        success: bool,
+        message: String,
+    },
 }
 
 impl WsActions {
@@ -13,13 +39,27 @@ impl WsActions {
             WsActions::ButtonPressed { .. } => "Button Pressed",
             WsActions::SetButtons { .. } => "Set Buttons",
             WsActions::SetButton { .. } => "Set Button",
+            WsActions::FlashButton { .. } => "Flash Button",
+            WsActions::ClientInfo { .. } => "Client Info",
+            WsActions::ActionResult { .. } => "Action Result",
         }
         .to_string()
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default)]
 pub struct SetButtonUI {
     pub image: Option<String>,
     pub color: Option<String>,
+    // rendered into image by the server, so clients never receive layers
+    pub layers: Option<Vec<types::Layer>>,
+}
+
+// DeckLayout describes the key grid of a client, sizes are in pixels of the device key images
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+pub struct DeckLayout {
+    pub rows: u8,
+    pub columns: u8,
+    pub key_size: u32,
+    pub key_gap: u32,
 }
@@ -0,0 +1,488 @@
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use image::{self, Pixel, Rgba, RgbaImage};
+use sdc_core::types::{BadgePosition, DeckLayout, GradientDirection, Layer, SetButtonUI};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+// size of the images sent to clients, the client resizes to fit the key
+pub const KEY_IMAGE_SIZE: u32 = 100;
+const BADGE_DEFAULT_SIZE: u32 = 20;
+const BADGE_MARGIN: u32 = 6;
+const PROGRESS_HEIGHT: u32 = 12;
+const FLASH_SHADE_ALPHA: u8 = 120;
+const FLASH_LINE_WIDTH: f32 = 9.0;
+// bounds for layouts sent by clients, the largest decks have 4x8 keys
+const MAX_LAYOUT_KEYS_PER_SIDE: u8 = 16;
+const MAX_LAYOUT_KEY_SIZE: u32 = 1024;
+
+pub type ImageCache = Arc<RwLock<HashMap<String, String>>>;
+// start and end of a line of the flash symbol, as fractions of the key size
+type FlashLine = ((f32, f32), (f32, f32));
+
+// BackgroundTile is the slice of a profile background that sits under a single key
+pub struct BackgroundTile {
+    cache_key: String,
+    image: RgbaImage,
+}
+
+// render_button returns the base64 encoded image for a button state, or none if the client should
+// only use the color
+pub async fn render_button(
+    button_state: &SetButtonUI,
+    tile: Option<&BackgroundTile>,
+    image_cache: &ImageCache,
+) -> Result<Option<String>> {
+    match (&button_state.layers, &button_state.image, tile) {
+        (Some(layers), _, _) => render_layers(layers, tile, image_cache).await.map(Some),
+        (None, Some(image), None) => get_image(image, button_state, image_cache).await.map(Some),
+        // over a tile the color is drawn as a layer, so colors with an alpha channel let the tile show
+        // through instead of hiding it
+        (None, image, Some(_)) => {
+            let layers: Vec<Layer> = button_state
+                .color
+                .iter()
+                .map(|color| Layer::Color {
+                    color: color.to_string(),
+                })
+                .chain(image.iter().map(|image| Layer::Image {
+                    image: image.to_string(),
+                }))
+                .collect();
+            render_layers(&layers, tile, image_cache).await.map(Some)
+        }
+        (None, None, None) => Ok(None),
+    }
+}
+
+// check_layout rejects layouts a background can't be sliced for, clients send them unchecked
+pub fn check_layout(layout: &DeckLayout) -> Result<()> {
+    if layout.rows == 0 || layout.columns == 0 {
+        return Err(anyhow!("layout needs at least one row and column"));
+    }
+    if layout.rows > MAX_LAYOUT_KEYS_PER_SIDE || layout.columns > MAX_LAYOUT_KEYS_PER_SIDE {
+        return Err(anyhow!(
+            "layout can have at most {} rows and columns",
+            MAX_LAYOUT_KEYS_PER_SIDE
+        ));
+    }
+    if layout.key_size == 0 || layout.key_size > MAX_LAYOUT_KEY_SIZE {
+        return Err(anyhow!(
+            "layout key size must be between 1 and {}",
+            MAX_LAYOUT_KEY_SIZE
+        ));
+    }
+    if layout.key_gap > layout.key_size {
+        return Err(anyhow!("layout key gap can't be larger than the key size"));
+    }
+    Ok(())
+}
+
+// background_size returns the gap between tiles and the size of the whole background, scaled to
+// the size of the images sent to clients
+fn background_size(layout: &DeckLayout) -> Option<(u32, u32, u32)> {
+    let gap = layout.key_gap.checked_mul(KEY_IMAGE_SIZE)? / layout.key_size.max(1);
+    let step = KEY_IMAGE_SIZE.checked_add(gap)?;
+    let width = (layout.columns as u32)
+        .checked_mul(step)?
+        .checked_sub(gap)?;
+    let height = (layout.rows as u32).checked_mul(step)?.checked_sub(gap)?;
+    Some((gap, width, height))
+}
+
+// background_tiles slices the background into one tile per key, skipping the gaps between keys so
+// the image lines up across the physical deck
+pub async fn background_tiles(
+    background: &String,
+    layout: &DeckLayout,
+    image_cache: &ImageCache,
+) -> Result<Vec<BackgroundTile>> {
+    let keys = layout.rows as usize * layout.columns as usize;
+    let cache_keys: Vec<String> = (0..keys)
+        .map(|i| {
+            format!(
+                "background-{}-{}x{}-{}-{}-{}",
+                background, layout.rows, layout.columns, layout.key_size, layout.key_gap, i
+            )
+        })
+        .collect();
+
+    {
+        let cached_locked = image_cache.read().await;
+        let cached_tiles: Option<Vec<&String>> = cache_keys
+            .iter()
+            .map(|key| cached_locked.get(key))
+            .collect();
+        if let Some(cached_tiles) = cached_tiles {
+            return cached_tiles
+                .into_iter()
+                .zip(cache_keys.iter())
+                .map(|(tile, cache_key)| {
+                    Ok(BackgroundTile {
+                        cache_key: cache_key.to_string(),
+                        image: decode_image(tile)?.to_rgba8(),
+                    })
+                })
+                .collect();
+        }
+    }
+
+    info!(background, ?layout, "slicing background image");
+    check_layout(layout)?;
+    let (gap, width, height) =
+        background_size(layout).ok_or_else(|| anyhow!("layout {:?} is too large", layout))?;
+    let loaded_image = fetch_image(background).await?.resize_to_fill(
+        width,
+        height,
+        image::imageops::FilterType::Triangle,
+    );
+
+    let mut tiles = Vec::new();
+    let mut cached_locked = image_cache.write().await;
+    for (i, cache_key) in cache_keys.into_iter().enumerate() {
+        let column = (i % layout.columns as usize) as u32;
+        let row = (i / layout.columns as usize) as u32;
+        let tile = loaded_image.crop_imm(
+            column * (KEY_IMAGE_SIZE + gap),
+            row * (KEY_IMAGE_SIZE + gap),
+            KEY_IMAGE_SIZE,
+            KEY_IMAGE_SIZE,
+        );
+
+        cached_locked.insert(cache_key.to_string(), encode_image(&tile)?);
+        tiles.push(BackgroundTile {
+            cache_key,
+            image: tile.to_rgba8(),
+        });
+    }
+
+    Ok(tiles)
+}
+
+async fn get_image(
+    image: &String,
+    button_state: &SetButtonUI,
+    image_cache: &ImageCache,
+) -> Result<String> {
+    let cache_key = format!(
+        "{}-{}",
+        image,
+        button_state.color.as_ref().unwrap_or(&"".to_string())
+    );
+
+    {
+        let cached_locked = image_cache.read().await;
+        if let Some(cached_image) = cached_locked.get(&cache_key) {
+            return Ok(cached_image.to_string());
+        }
+    }
+    let mut loaded_image = fetch_image(image).await?;
+
+    // apply background if color is also set
+    // steal this from the streamdeck library, to avoid it as a dependency for the api
+    info!(image=?image, color=?button_state.color, "loading image");
+    if let Some(color) = &button_state.color {
+        let (r, g, b) = hex_color_components_from_str(color)
+            .map_err(|err| anyhow!("unable to decoed hex color: {}", err))?;
+        let rgba = loaded_image.as_mut_rgba8().ok_or_else(|| {
+            anyhow!("unable to convert image to have transparent layer, is it a png?")
+        })?;
+
+        let mut r = image::Rgba([r, g, b, 0]);
+        for p in rgba.pixels_mut() {
+            r.0[3] = 255 - p.0[3];
+
+            p.blend(&r);
+        }
+    }
+
+    let base64_encoded = encode_image(&loaded_image.resize(
+        KEY_IMAGE_SIZE,
+        KEY_IMAGE_SIZE,
+        image::imageops::FilterType::Nearest,
+    ))?;
+    image_cache
+        .write()
+        .await
+        .insert(cache_key, base64_encoded.to_string());
+    Ok(base64_encoded)
+}
+
+// render_layers draws the layers bottom to top onto the tile, or a black key, and returns it base64
+// encoded
+async fn render_layers(
+    layers: &[Layer],
+    tile: Option<&BackgroundTile>,
+    image_cache: &ImageCache,
+) -> Result<String> {
+    let cache_key = match tile {
+        Some(tile) => format!("{}-{}", tile.cache_key, serde_json::to_string(layers)?),
+        None => serde_json::to_string(layers)?,
+    };
+    if let Some(cached_image) = image_cache.read().await.get(&cache_key) {
+        return Ok(cached_image.to_string());
+    }
+
+    let mut canvas = match tile {
+        Some(tile) => tile.image.clone(),
+        None => RgbaImage::from_pixel(KEY_IMAGE_SIZE, KEY_IMAGE_SIZE, Rgba([0, 0, 0, 255])),
+    };
+    for layer in layers {
+        draw_layer(&mut canvas, layer).await?;
+    }
+
+    let base64_encoded = encode_image(&image::DynamicImage::ImageRgba8(canvas))?;
+    image_cache
+        .write()
+        .await
+        .insert(cache_key, base64_encoded.to_string());
+    Ok(base64_encoded)
+}
+
+async fn draw_layer(canvas: &mut RgbaImage, layer: &Layer) -> Result<()> {
+    let (width, height) = canvas.dimensions();
+
+    match layer {
+        Layer::Color { color } => {
+            let color = rgba_from_hex(color)?;
+            for p in canvas.pixels_mut() {
+                p.blend(&color);
+            }
+        }
+        Layer::Gradient {
+            from,
+            to,
+            direction,
+        } => {
+            let from = rgba_from_hex(from)?;
+            let to = rgba_from_hex(to)?;
+            for (x, y, p) in canvas.enumerate_pixels_mut() {
+                let t = match direction {
+                    GradientDirection::Vertical => y as f32 / (height - 1) as f32,
+                    GradientDirection::Horizontal => x as f32 / (width - 1) as f32,
+                    GradientDirection::Diagonal => (x + y) as f32 / (width + height - 2) as f32,
+                };
+                p.blend(&lerp_color(&from, &to, t));
+            }
+        }
+        Layer::Image { image } => {
+            let loaded_image = fetch_image(image)
+                .await?
+                .resize(width, height, image::imageops::FilterType::Nearest)
+                .to_rgba8();
+            // center the image, resize keeps the aspect ratio so one side may be smaller
+            let x = (width - loaded_image.width()) / 2;
+            let y = (height - loaded_image.height()) / 2;
+            image::imageops::overlay(canvas, &loaded_image, x.into(), y.into());
+        }
+        Layer::Badge {
+            color,
+            position,
+            size,
+        } => {
+            let color = rgba_from_hex(color)?;
+            let radius = size.unwrap_or(BADGE_DEFAULT_SIZE) as f32 / 2.0;
+            let offset = BADGE_MARGIN as f32 + radius;
+            let (cx, cy) = match position {
+                BadgePosition::TopLeft => (offset, offset),
+                BadgePosition::TopRight => (width as f32 - offset, offset),
+                BadgePosition::BottomLeft => (offset, height as f32 - offset),
+                BadgePosition::BottomRight => (width as f32 - offset, height as f32 - offset),
+            };
+            for (x, y, p) in canvas.enumerate_pixels_mut() {
+                let dx = x as f32 + 0.5 - cx;
+                let dy = y as f32 + 0.5 - cy;
+                if dx * dx + dy * dy <= radius * radius {
+                    p.blend(&color);
+                }
+            }
+        }
+        Layer::Progress {
+            value,
+            max,
+            color,
+            background,
+        } => {
+            let value = value
+                .number()
+                .ok_or_else(|| anyhow!("progress value {:?} was not resolved", value))?;
+            let fraction = (value / max.unwrap_or(100.0)).clamp(0.0, 1.0);
+            let filled_width = (fraction * width as f32).round() as u32;
+            let color = rgba_from_hex(color)?;
+            let background = match background {
+                Some(background) => Some(rgba_from_hex(background)?),
+                None => None,
+            };
+            for (x, y, p) in canvas.enumerate_pixels_mut() {
+                if y < height - PROGRESS_HEIGHT {
+                    continue;
+                }
+                if x < filled_width {
+                    p.blend(&color);
+                } else if let Some(background) = &background {
+                    p.blend(background);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// render_result_flash dims the rendered button and draws a check or a cross over it, it is shown
+// briefly after a button press to confirm the outcome of the actions
+pub fn render_result_flash(button: &SetButtonUI, success: bool) -> Result<String> {
+    let mut canvas = match (&button.image, &button.color) {
+        (Some(image), _) => decode_image(image)?
+            .resize_exact(
+                KEY_IMAGE_SIZE,
+                KEY_IMAGE_SIZE,
+                image::imageops::FilterType::Nearest,
+            )
+            .to_rgba8(),
+        (None, Some(color)) => {
+            RgbaImage::from_pixel(KEY_IMAGE_SIZE, KEY_IMAGE_SIZE, rgba_from_hex(color)?)
+        }
+        (None, None) => RgbaImage::from_pixel(KEY_IMAGE_SIZE, KEY_IMAGE_SIZE, Rgba([0, 0, 0, 255])),
+    };
+
+    let shade = Rgba([0, 0, 0, FLASH_SHADE_ALPHA]);
+    for p in canvas.pixels_mut() {
+        p.blend(&shade);
+    }
+
+    let (color, lines): (Rgba<u8>, &[FlashLine]) = match success {
+        true => (
+            Rgba([46, 204, 64, 255]),
+            &[((0.25, 0.52), (0.42, 0.7)), ((0.42, 0.7), (0.76, 0.32))],
+        ),
+        false => (
+            Rgba([255, 65, 54, 255]),
+            &[((0.28, 0.28), (0.72, 0.72)), ((0.72, 0.28), (0.28, 0.72))],
+        ),
+    };
+
+    let size = KEY_IMAGE_SIZE as f32;
+    let half_width = FLASH_LINE_WIDTH / 2.0;
+    for (x, y, p) in canvas.enumerate_pixels_mut() {
+        let point = (x as f32 + 0.5, y as f32 + 0.5);
+        let on_line = lines.iter().any(|(a, b)| {
+            let a = (a.0 * size, a.1 * size);
+            let b = (b.0 * size, b.1 * size);
+            distance_to_segment(point, a, b) <= half_width
+        });
+        if on_line {
+            p.blend(&color);
+        }
+    }
+
+    encode_image(&image::DynamicImage::ImageRgba8(canvas))
+}
+
+fn distance_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+    let (cx, cy) = (a.0 + t * dx, a.1 + t * dy);
+    ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt()
+}
+
+fn lerp_color(from: &Rgba<u8>, to: &Rgba<u8>, t: f32) -> Rgba<u8> {
+    let mut color = *from;
+    for (c, (a, b)) in color.0.iter_mut().zip(from.0.iter().zip(to.0.iter())) {
+        *c = (*a as f32 + (*b as f32 - *a as f32) * t).round() as u8;
+    }
+    color
+}
+
+fn rgba_from_hex(s: &str) -> Result<Rgba<u8>> {
+    let (r, g, b) = hex_color_components_from_str(s)
+        .map_err(|err| anyhow!("unable to decode hex color: {}", err))?;
+    let a = match s.len() {
+        8 => u8::from_str_radix(&s[6..8], 16).map_err(|e| anyhow!("int parsing error: {}", e))?,
+        _ => 255,
+    };
+    Ok(Rgba([r, g, b, a]))
+}
+
+fn encode_image(image: &image::DynamicImage) -> Result<String> {
+    let mut buffered_image = Vec::new();
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut buffered_image),
+            image::ImageOutputFormat::Png,
+        )
+        .map_err(|err| anyhow!("unable to write image to buffer: {}", err))?;
+
+    Ok(base64::engine::general_purpose::STANDARD.encode(&buffered_image))
+}
+
+fn decode_image(image: &str) -> Result<image::DynamicImage> {
+    let image_bytes = base64::engine::general_purpose::STANDARD
+        .decode(image)
+        .map_err(|err| anyhow!("unable to decode image from base64: {}", err))?;
+    Ok(image::load_from_memory(&image_bytes)?)
+}
+
+async fn fetch_image(image: &String) -> Result<image::DynamicImage> {
+    if image.starts_with("http") {
+        return load_image_from_url(image).await;
+    }
+
+    let loaded_image = image::open(image)?;
+    Ok(loaded_image)
+}
+
+async fn load_image_from_url(image: &String) -> Result<image::DynamicImage> {
+    let img_bytes = reqwest::get(image).await?.bytes().await?;
+    let image = image::load_from_memory(&img_bytes)?;
+    Ok(image)
+}
+
+// steal this from the streamdeck library, to avoid it as a dependency for the api
+fn hex_color_components_from_str(s: &str) -> Result<(u8, u8, u8)> {
+    // also guarantees the slices below fall on char boundaries
+    if (s.len() != 6 && s.len() != 8) || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Expected colour in the hex form: RRGGBB"));
+    }
+
+    let r = u8::from_str_radix(&s[0..2], 16).map_err(|e| anyhow!("int parsing error: {}", e))?;
+    let g = u8::from_str_radix(&s[2..4], 16).map_err(|e| anyhow!("int parsing error: {}", e))?;
+    let b = u8::from_str_radix(&s[4..6], 16).map_err(|e| anyhow!("int parsing error: {}", e))?;
+
+    Ok((r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(rows: u8, columns: u8, key_size: u32, key_gap: u32) -> DeckLayout {
+        DeckLayout {
+            rows,
+            columns,
+            key_size,
+            key_gap,
+        }
+    }
+
+    #[test]
+    fn check_layout_bounds() {
+        assert!(check_layout(&layout(3, 5, 72, 16)).is_ok());
+        assert!(check_layout(&layout(0, 5, 72, 16)).is_err());
+        assert!(check_layout(&layout(3, 0, 72, 16)).is_err());
+        assert!(check_layout(&layout(255, 255, 72, 16)).is_err());
+        assert!(check_layout(&layout(3, 5, 0, 0)).is_err());
+        assert!(check_layout(&layout(3, 5, 72, u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn background_size_scales_gap() {
+        assert_eq!(
+            background_size(&layout(3, 5, 72, 18)),
+            Some((25, 5 * 125 - 25, 3 * 125 - 25))
+        );
+        assert_eq!(background_size(&layout(3, 5, 1, u32::MAX)), None);
+    }
+}
@@ -0,0 +1,867 @@
+use crate::state;
+use crate::sun::{Location, TimeOfDay};
+use crate::template;
+use crate::ws_api;
+use crate::Config;
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveTime};
+use futures_util::future::{self, BoxFuture};
+use integrations::{Integration, IntegrationDescription, IntegrationEnum, IntoIntegration};
+use sdc_core::types::{
+    Action, ActionModifiers, Actions, Comparison, Condition, ExecuteActionReq, IntegrationAction,
+    Layer, LayerValue, TimeCondition,
+};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+const ACTION_SPLIT_CHARS: [char; 2] = [':', ':'];
+// how often integrations reload entities such as light names from their service
+const REFRESH_INTERVAL_MIN: u64 = 5;
+// how often unhealthy integrations are refreshed to see if they recovered
+const HEALTH_CHECK_INTERVAL_SEC: u64 = 60;
+// integrations that fail to set up are retried with a backoff between these
+const INIT_RETRY_MIN_SEC: u64 = 30;
+const INIT_RETRY_MAX_SEC: u64 = 600;
+// guards against macros that (indirectly) run themselves
+pub(crate) const MAX_MACRO_DEPTH: usize = 8;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum VarScope {
+    #[default]
+    Global,
+    // only visible to the client that ran the action, which must report a name so the variables
+    // are still its own after a reconnect
+    Client,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct VarAction {
+    name: String,
+    #[serde(default)]
+    scope: VarScope,
+    // used by var::set
+    value: Option<Value>,
+    // used by var::incr, defaults to 1
+    by: Option<f64>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ActionContext {
+    requestor_uuid: Option<uuid::Uuid>,
+    macro_depth: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum IntegrationHealth {
+    Initializing,
+    Healthy,
+    Unhealthy { error: String },
+}
+
+pub struct IntegrationManager {
+    // only contains integrations that were set up successfully
+    integrations: RwLock<HashMap<String, Arc<IntegrationEnum>>>,
+    // contains every configured integration
+    health: RwLock<HashMap<String, IntegrationHealth>>,
+    // bumped whenever an integration reports that its state changed
+    state_changes: watch::Sender<()>,
+    ws_clients: ws_api::Clients,
+    config: Arc<Config>,
+    state: state::State,
+}
+
+impl IntegrationManager {
+    // new doesn't set up the integrations, see start_integration_init
+    pub fn new(
+        ws_clients: ws_api::Clients,
+        config_ref: &Arc<Config>,
+        state: state::State,
+    ) -> Result<(
+        IntegrationManager,
+        Sender<ExecuteActionReq>,
+        Receiver<ExecuteActionReq>,
+    )> {
+        let (tx, rx) = mpsc::channel::<ExecuteActionReq>(32);
+
+        let mut health = HashMap::new();
+        for integration in &config_ref.integrations {
+            let name = integration.name();
+            if health.contains_key(&name) {
+                return Err(anyhow!(
+                    "integration name {} is used more than once, set a unique name",
+                    name
+                ));
+            }
+            health.insert(name, IntegrationHealth::Initializing);
+        }
+
+        let manager = IntegrationManager {
+            integrations: RwLock::new(HashMap::new()),
+            health: RwLock::new(health),
+            state_changes: watch::channel(()).0,
+            ws_clients,
+            config: config_ref.clone(),
+            state,
+        };
+
+        Ok((manager, tx, rx))
+    }
+
+    // health returns the status of every configured integration
+    pub async fn health(&self) -> BTreeMap<String, IntegrationHealth> {
+        self.health
+            .read()
+            .await
+            .iter()
+            .map(|(name, health)| (name.clone(), health.clone()))
+            .collect()
+    }
+
+    // subscribe_state_changes returns a receiver that is marked changed whenever an integration
+    // pushes a state change, so state can be reevaluated right away instead of on the next poll
+    pub fn subscribe_state_changes(&self) -> watch::Receiver<()> {
+        self.state_changes.subscribe()
+    }
+
+    async fn set_health(&self, name: &str, health: IntegrationHealth) {
+        self.health.write().await.insert(name.to_string(), health);
+    }
+
+    // integration returns the named integration if it is healthy, so actions for an unavailable
+    // integration fail right away rather than waiting on a service that is down
+    async fn integration(&self, name: &str) -> Result<Arc<IntegrationEnum>> {
+        match self.health.read().await.get(name) {
+            Some(IntegrationHealth::Healthy) => (),
+            Some(IntegrationHealth::Initializing) => {
+                return Err(anyhow!("integration {} is still initializing", name))
+            }
+            Some(IntegrationHealth::Unhealthy { error }) => {
+                return Err(anyhow!("integration {} is unavailable: {}", name, error))
+            }
+            None => return Err(anyhow!("unknown integration {}", name)),
+        }
+
+        self.integrations
+            .read()
+            .await
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("integration {} is still initializing", name))
+    }
+
+    // execute_actions runs the actions in order, stopping at the first error
+    pub async fn execute_actions(
+        &self,
+        requestor_uuid: Option<uuid::Uuid>,
+        actions: &Actions,
+    ) -> Result<()> {
+        let ctx = ActionContext {
+            requestor_uuid,
+            macro_depth: 0,
+        };
+        self.run_actions(ctx, actions).await
+    }
+
+    async fn run_actions(&self, ctx: ActionContext, actions: &Actions) -> Result<()> {
+        for action in actions {
+            self.execute_action(ctx, action).await?;
+        }
+
+        Ok(())
+    }
+
+    // boxed since actions can be nested
+    fn execute_action<'a>(
+        &'a self,
+        ctx: ActionContext,
+        action: &'a Action,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match action {
+                Action::Delay(delay_action) => {
+                    tokio::time::sleep(Duration::from_millis(delay_action.delay_ms)).await;
+                    Ok(())
+                }
+                Action::Parallel(parallel_action) => {
+                    let parallel = async {
+                        let results = future::join_all(
+                            parallel_action
+                                .parallel
+                                .iter()
+                                .map(|action| self.execute_action(ctx, action)),
+                        )
+                        .await;
+
+                        let errors: Vec<String> = results
+                            .into_iter()
+                            .filter_map(|result| result.err())
+                            .map(|err| err.to_string())
+                            .collect();
+                        if !errors.is_empty() {
+                            return Err(anyhow!(
+                                "{} parallel actions failed: {}",
+                                errors.len(),
+                                errors.join(", ")
+                            ));
+                        }
+                        Ok(())
+                    };
+
+                    apply_modifiers(&parallel_action.modifiers, "parallel", parallel).await
+                }
+                Action::Conditional(conditional_action) => {
+                    let actions = if self
+                        .evaluate_condition(&conditional_action.condition, ctx.requestor_uuid)
+                        .await?
+                    {
+                        &conditional_action.then
+                    } else {
+                        &conditional_action.otherwise
+                    };
+                    self.run_actions(ctx, actions).await
+                }
+                Action::Integration(integration_action) => {
+                    apply_modifiers(
+                        &integration_action.modifiers,
+                        &integration_action.action,
+                        self.execute_integration_action(ctx, integration_action),
+                    )
+                    .await
+                }
+            }
+        })
+    }
+
+    // describe only includes integrations that have been set up
+    pub async fn describe(&self) -> Vec<IntegrationDescription> {
+        let integrations: Vec<_> = self.integrations.read().await.values().cloned().collect();
+        let mut descriptions = Vec::new();
+        for integration in integrations {
+            descriptions.push(integration.describe().await);
+        }
+        descriptions.sort_by(|a, b| a.name.cmp(&b.name));
+        descriptions
+    }
+
+    pub async fn get_state(&self, integration_name: &str, query: Value) -> Result<Value> {
+        self.integration(integration_name)
+            .await?
+            .get_state(query)
+            .await
+    }
+
+    // resolve_layers fills in the values of bound layers, eg a progress bar showing the brightness of
+    // a light. Values that can't be resolved yet, eg while an integration starts, are shown as 0.
+    pub async fn resolve_layers(
+        &self,
+        layers: &[Layer],
+        requestor_uuid: Option<uuid::Uuid>,
+    ) -> Vec<Layer> {
+        let mut resolved = Vec::new();
+        for layer in layers {
+            resolved.push(match layer {
+                Layer::Progress {
+                    value,
+                    max,
+                    color,
+                    background,
+                } if layer.is_bound() => {
+                    let value = self
+                        .resolve_layer_value(value, requestor_uuid)
+                        .await
+                        .unwrap_or_else(|err| {
+                            warn!(error = ?err, ?value, "failed to resolve layer value");
+                            0.0
+                        });
+                    Layer::Progress {
+                        value: LayerValue::Number(value),
+                        max: *max,
+                        color: color.clone(),
+                        background: background.clone(),
+                    }
+                }
+                layer => layer.clone(),
+            });
+        }
+        resolved
+    }
+
+    async fn resolve_layer_value(
+        &self,
+        value: &LayerValue,
+        requestor_uuid: Option<uuid::Uuid>,
+    ) -> Result<f32> {
+        let resolved = match value {
+            LayerValue::Number(n) => return Ok(*n),
+            LayerValue::Placeholder(placeholder) => template::interpolate(
+                &Value::String(placeholder.to_string()),
+                &self.template_context(requestor_uuid).await,
+            )?,
+            LayerValue::State(state_value) => {
+                let query = template::interpolate(
+                    &state_value.query,
+                    &self.template_context(requestor_uuid).await,
+                )?;
+                let state = self.get_state(&state_value.integration, query).await?;
+                match &state_value.pointer {
+                    Some(pointer) => state.pointer(pointer).cloned().unwrap_or_default(),
+                    None => state,
+                }
+            }
+        };
+
+        match &resolved {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+        .map(|n| n as f32)
+        .ok_or_else(|| anyhow!("{} is not a number", resolved))
+    }
+
+    // template_context is what placeholders are filled in from, the vars visible to the client
+    async fn template_context(&self, requestor_uuid: Option<uuid::Uuid>) -> Value {
+        let vars = self
+            .state
+            .vars(self.client_name(requestor_uuid).await.as_deref())
+            .await;
+        serde_json::json!({ "vars": vars })
+    }
+
+    // evaluate_condition fills in placeholders in condition with the vars visible to the client,
+    // global vars when there is none, and checks whether it holds
+    pub async fn evaluate_condition(
+        &self,
+        condition: &Condition,
+        requestor_uuid: Option<uuid::Uuid>,
+    ) -> Result<bool> {
+        // round trip through json so placeholders can be used anywhere in the condition
+        let condition: Condition = serde_json::from_value(template::interpolate(
+            &serde_json::to_value(condition)?,
+            &self.template_context(requestor_uuid).await,
+        )?)?;
+        self.evaluate_interpolated_condition(&condition).await
+    }
+
+    // boxed since conditions can be nested
+    fn evaluate_interpolated_condition<'a>(
+        &'a self,
+        condition: &'a Condition,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            match condition {
+                Condition::State(state_condition) => {
+                    let state = self
+                        .get_state(&state_condition.integration, state_condition.query.clone())
+                        .await?;
+
+                    let value = match &state_condition.pointer {
+                        Some(pointer) => state.pointer(pointer).unwrap_or(&Value::Null),
+                        None => &state,
+                    };
+                    Ok(compare(value, &state_condition.comparison))
+                }
+                Condition::Time(time_condition) => {
+                    time_matches(time_condition, self.config.location.as_ref())
+                }
+                Condition::All(conditions) => {
+                    for condition in conditions {
+                        if !self.evaluate_interpolated_condition(condition).await? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                }
+                Condition::Any(conditions) => {
+                    for condition in conditions {
+                        if self.evaluate_interpolated_condition(condition).await? {
+                            return Ok(true);
+                        }
+                    }
+                    Ok(false)
+                }
+                Condition::Not(condition) => {
+                    Ok(!self.evaluate_interpolated_condition(condition).await?)
+                }
+            }
+        })
+    }
+
+    async fn execute_integration_action(
+        &self,
+        ctx: ActionContext,
+        action: &IntegrationAction,
+    ) -> Result<()> {
+        let split_index = action.action.find(ACTION_SPLIT_CHARS);
+        let (integration_name, action_name) = match split_index {
+            Some(i) => (
+                &action.action[..i],
+                &action.action[i + ACTION_SPLIT_CHARS.len()..],
+            ),
+            None => {
+                return Err(anyhow!(
+                    "action {} was invalid, must contain separator.",
+                    action.action
+                ))
+            }
+        };
+
+        // vars are filled in right before running, so earlier actions can change them
+        let mut options = template::interpolate(
+            &action.options,
+            &self.template_context(ctx.requestor_uuid).await,
+        )?;
+
+        match integration_name {
+            "profile" => {
+                return self
+                    .execute_profile_action(ctx.requestor_uuid, action_name, &options)
+                    .await
+            }
+            "macro" => return self.execute_macro_action(ctx, action_name, &options).await,
+            "var" => return self.execute_var_action(ctx, action_name, options).await,
+            _ => (),
+        }
+
+        options["action"] = serde_json::Value::String(action_name.to_string());
+        self.integration(integration_name)
+            .await?
+            .execute_action(action_name.to_string(), options)
+            .await
+    }
+
+    async fn execute_profile_action(
+        &self,
+        requestor_uuid: Option<uuid::Uuid>,
+        action_name: &str,
+        options: &Value,
+    ) -> Result<()> {
+        if action_name != "set" {
+            return Err(anyhow!(
+                "unknown action for profile integration {}",
+                action_name
+            ));
+        }
+
+        let requestor_uuid = requestor_uuid
+            .ok_or_else(|| anyhow!("recieved profile action request for unknown requestor"))?;
+
+        let profile_value = options
+            .get("profile")
+            .ok_or_else(|| anyhow!("invalid profile selection"))?;
+
+        if let serde_json::Value::String(profile_name) = profile_value {
+            let mut clients = self.ws_clients.write().await;
+            let client = clients.get_mut(&requestor_uuid).ok_or_else(|| {
+                anyhow!("unable to get websocket client for {:?}", requestor_uuid)
+            })?;
+            client.profile = profile_name.to_string();
+        }
+
+        Ok(())
+    }
+
+    async fn execute_macro_action(
+        &self,
+        ctx: ActionContext,
+        action_name: &str,
+        options: &Value,
+    ) -> Result<()> {
+        if action_name != "run" {
+            return Err(anyhow!(
+                "unknown action for macro integration {}",
+                action_name
+            ));
+        }
+
+        let name = options
+            .get("name")
+            .and_then(|name| name.as_str())
+            .ok_or_else(|| anyhow!("macro::run requires a name"))?;
+        let params = options
+            .get("params")
+            .cloned()
+            .unwrap_or_else(|| serde_json::json!({}));
+
+        if ctx.macro_depth >= MAX_MACRO_DEPTH {
+            return Err(anyhow!(
+                "macro {} exceeded the maximum nesting depth of {}",
+                name,
+                MAX_MACRO_DEPTH
+            ));
+        }
+
+        let macro_actions = self
+            .config
+            .macros
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown macro {}", name))?;
+
+        // round trip through json so params can be substituted anywhere in the action options
+        let actions: Actions = serde_json::from_value(template::interpolate(
+            &serde_json::to_value(macro_actions)?,
+            &serde_json::json!({ "params": params }),
+        )?)?;
+
+        let ctx = ActionContext {
+            macro_depth: ctx.macro_depth + 1,
+            ..ctx
+        };
+        self.run_actions(ctx, &actions)
+            .await
+            .map_err(|err| anyhow!("macro {} failed: {}", name, err))
+    }
+
+    async fn execute_var_action(
+        &self,
+        ctx: ActionContext,
+        action_name: &str,
+        options: Value,
+    ) -> Result<()> {
+        let var_action: VarAction = serde_json::from_value(options)
+            .map_err(|err| anyhow!("invalid var action: {}", err))?;
+
+        let client = match var_action.scope {
+            VarScope::Global => None,
+            VarScope::Client => {
+                Some(self.client_name(ctx.requestor_uuid).await.ok_or_else(|| {
+                    anyhow!("client scoped variables require a requesting client with a name")
+                })?)
+            }
+        };
+
+        let value = self
+            .state
+            .update_var(
+                client.as_deref(),
+                &var_action.name,
+                |current| match action_name {
+                    "set" => var_action
+                        .value
+                        .clone()
+                        .ok_or_else(|| anyhow!("var::set requires a value")),
+                    "toggle" => Ok(Value::Bool(!current.is_some_and(is_truthy))),
+                    "incr" => {
+                        let mut n = current.and_then(|v| v.as_f64()).unwrap_or_default()
+                            + var_action.by.unwrap_or(1.0);
+                        if let Some(min) = var_action.min {
+                            n = n.max(min);
+                        }
+                        if let Some(max) = var_action.max {
+                            n = n.min(max);
+                        }
+                        Ok(number_value(n))
+                    }
+                    _ => Err(anyhow!(
+                        "unknown action for var integration {}",
+                        action_name
+                    )),
+                },
+            )
+            .await?;
+
+        info!(name = var_action.name, ?client, %value, "updated variable");
+        // buttons can show variables, eg in a progress bar
+        ws_api::resync_clients(&self.ws_clients).await;
+        Ok(())
+    }
+
+    // client_name identifies the requesting client for client scoped variables. Clients without a
+    // name have none, their uuid changes with every connection.
+    async fn client_name(&self, requestor_uuid: Option<uuid::Uuid>) -> Option<String> {
+        let uuid = requestor_uuid?;
+        self.ws_clients.read().await.get(&uuid)?.name.clone()
+    }
+}
+
+// number_value keeps whole numbers as integers so they interpolate as eg 3 rather than 3.0
+fn number_value(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::from(n as i64)
+    } else {
+        Value::from(n)
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+// compare checks value against every comparison that is set, strings are compared case
+// insensitively. Without any comparisons the value must be truthy.
+fn compare(value: &Value, comparison: &Comparison) -> bool {
+    let values_equal = |expected: &Value| match (value, expected) {
+        (Value::String(a), Value::String(b)) => a.eq_ignore_ascii_case(b),
+        // numbers compare by value, so 100 equals a state of 100.0
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (a, b) => a == b,
+    };
+
+    if comparison.equals.is_none()
+        && comparison.not_equals.is_none()
+        && comparison.greater_than.is_none()
+        && comparison.less_than.is_none()
+    {
+        return is_truthy(value);
+    }
+
+    comparison.equals.as_ref().is_none_or(values_equal)
+        && comparison
+            .not_equals
+            .as_ref()
+            .is_none_or(|expected| !values_equal(expected))
+        && comparison
+            .greater_than
+            .is_none_or(|min| value.as_f64().is_some_and(|v| v > min))
+        && comparison
+            .less_than
+            .is_none_or(|max| value.as_f64().is_some_and(|v| v < max))
+}
+
+fn time_matches(condition: &TimeCondition, location: Option<&Location>) -> Result<bool> {
+    let now = Local::now();
+    let parse = |time: &Option<String>| -> Result<Option<NaiveTime>> {
+        time.as_ref()
+            .map(|t| {
+                t.parse::<TimeOfDay>()?
+                    .on(location, now.date_naive())?
+                    .map(|at| at.time())
+                    .ok_or_else(|| anyhow!("{} does not happen today", t))
+            })
+            .transpose()
+    };
+    let now = now.time();
+
+    Ok(
+        match (parse(&condition.after)?, parse(&condition.before)?) {
+            (Some(after), Some(before)) if after <= before => now >= after && now < before,
+            // range wraps around midnight, eg 22:00 to 06:00
+            (Some(after), Some(before)) => now >= after || now < before,
+            (Some(after), None) => now >= after,
+            (None, Some(before)) => now < before,
+            (None, None) => true,
+        },
+    )
+}
+
+async fn apply_modifiers<F>(modifiers: &ActionModifiers, name: &str, action: F) -> Result<()>
+where
+    F: std::future::Future<Output = Result<()>>,
+{
+    let result = match modifiers.timeout_ms {
+        Some(timeout_ms) => tokio::time::timeout(Duration::from_millis(timeout_ms), action)
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("{} timed out after {}ms", name, timeout_ms))),
+        None => action.await,
+    };
+
+    match result {
+        Err(err) if modifiers.continue_on_error => {
+            warn!(error = ?err, action = name, "action failed, continuing");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// start_integration_init sets up every integration in the background. Integrations that fail are
+// marked unhealthy and retried with a backoff, so one unreachable service doesn't stop the server.
+pub fn start_integration_init(integration_manager: Arc<IntegrationManager>) {
+    for index in 0..integration_manager.config.integrations.len() {
+        let integration_manager = integration_manager.clone();
+        tokio::spawn(async move {
+            let config = &integration_manager.config.integrations[index];
+            let name = config.name();
+            let mut retry_sec = INIT_RETRY_MIN_SEC;
+
+            loop {
+                info!(integration = name, "setting up integration");
+                match config.into_integration().await {
+                    Ok(integration) => {
+                        if let Some(changes) = integration.state_changes() {
+                            tokio::spawn(forward_state_changes(
+                                integration_manager.clone(),
+                                changes,
+                            ));
+                        }
+                        integration_manager
+                            .integrations
+                            .write()
+                            .await
+                            .insert(name.clone(), Arc::new(integration));
+                        integration_manager
+                            .set_health(&name, IntegrationHealth::Healthy)
+                            .await;
+                        info!(integration = name, "integration is ready");
+                        return;
+                    }
+                    Err(err) => {
+                        error!(error = ?err, integration = name, retry_sec, "failed to set up integration");
+                        integration_manager
+                            .set_health(
+                                &name,
+                                IntegrationHealth::Unhealthy {
+                                    error: err.to_string(),
+                                },
+                            )
+                            .await;
+                    }
+                }
+
+                tokio::time::sleep(Duration::from_secs(retry_sec)).await;
+                retry_sec = (retry_sec * 2).min(INIT_RETRY_MAX_SEC);
+            }
+        });
+    }
+}
+
+// forward_state_changes re-evaluates profile rules and rerenders the buttons of every client when
+// an integration reports a state change, so buttons showing that state are up to date
+async fn forward_state_changes(
+    integration_manager: Arc<IntegrationManager>,
+    mut changes: tokio::sync::broadcast::Receiver<()>,
+) {
+    loop {
+        match changes.recv().await {
+            // lagging only means several changes were merged into one
+            Ok(()) | Err(RecvError::Lagged(_)) => {
+                // changes often come in bursts, eg while dimming, which only need one resync
+                while let Ok(()) | Err(TryRecvError::Lagged(_)) = changes.try_recv() {}
+                integration_manager.state_changes.send_modify(|_| ());
+                ws_api::resync_clients(&integration_manager.ws_clients).await;
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+// start_integration_refresh periodically refreshes every integration so renamed or new entities
+// are picked up without a restart. Unhealthy integrations are refreshed more often so they recover
+// soon after their service comes back.
+pub fn start_integration_refresh(integration_manager: Arc<IntegrationManager>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let refresh_every = REFRESH_INTERVAL_MIN * 60 / HEALTH_CHECK_INTERVAL_SEC;
+        let mut tick: u64 = 0;
+        loop {
+            tokio::time::sleep(Duration::from_secs(HEALTH_CHECK_INTERVAL_SEC)).await;
+            tick += 1;
+
+            let integrations: Vec<_> = integration_manager
+                .integrations
+                .read()
+                .await
+                .iter()
+                .map(|(name, integration)| (name.clone(), integration.clone()))
+                .collect();
+            for (name, integration) in integrations {
+                let healthy = matches!(
+                    integration_manager.health.read().await.get(&name),
+                    Some(IntegrationHealth::Healthy)
+                );
+                if healthy && !tick.is_multiple_of(refresh_every) {
+                    continue;
+                }
+
+                let health = match integration.refresh().await {
+                    Ok(()) => IntegrationHealth::Healthy,
+                    Err(err) => {
+                        error!(error = ?err, integration = name, "failed to refresh integration");
+                        IntegrationHealth::Unhealthy {
+                            error: err.to_string(),
+                        }
+                    }
+                };
+                integration_manager.set_health(&name, health).await;
+            }
+        }
+    })
+}
+
+pub fn start_integration_manager(
+    integration_manager: Arc<IntegrationManager>,
+    mut rx: Receiver<ExecuteActionReq>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Start receiving messages
+        while let Some(execute_actions_req) = rx.recv().await {
+            // run each request in its own task so delays and slow integrations don't hold up
+            // other buttons
+            let integration_manager = integration_manager.clone();
+            tokio::spawn(async move {
+                let response = integration_manager
+                    .execute_actions(
+                        execute_actions_req.requestor_uuid,
+                        &execute_actions_req.actions,
+                    )
+                    .await;
+                if let Err(e) = &response {
+                    info!("error executing request: {}", e);
+                }
+                // okay to eat this error, since that means the reciever is closed
+                let _ = execute_actions_req.tx.send(response);
+            });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn comparison(value: Value) -> Comparison {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn equals_compares_numbers_by_value() {
+        assert!(compare(&json!(100.0), &comparison(json!({"equals": 100}))));
+        assert!(compare(&json!(100), &comparison(json!({"equals": 100.0}))));
+        assert!(!compare(&json!(99.5), &comparison(json!({"equals": 100}))));
+        assert!(compare(
+            &json!(99.5),
+            &comparison(json!({"not_equals": 100}))
+        ));
+    }
+
+    #[test]
+    fn equals_ignores_string_case() {
+        assert!(compare(
+            &json!("Playing"),
+            &comparison(json!({"equals": "playing"}))
+        ));
+        assert!(!compare(&json!("100"), &comparison(json!({"equals": 100}))));
+    }
+
+    #[test]
+    fn range_comparisons() {
+        let between = comparison(json!({"greater_than": 10, "less_than": 50}));
+        assert!(compare(&json!(20), &between));
+        assert!(!compare(&json!(50), &between));
+        assert!(!compare(&json!("20"), &between));
+    }
+
+    #[test]
+    fn no_comparison_checks_truthiness() {
+        assert!(compare(&json!(true), &Comparison::default()));
+        assert!(!compare(&json!(0), &Comparison::default()));
+    }
+}
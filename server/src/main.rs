@@ -1,55 +1,111 @@
-use anyhow::{anyhow, Result};
-use integrations::{Integration, IntegrationEnum, IntegrationsConfigurationEnum, IntoIntegration};
-use sdc_core::types::{Actions, ExecuteActionReq, Profiles};
+use anyhow::Result;
+use integration_manager::IntegrationManager;
+use integrations::IntegrationsConfigurationEnum;
+use sdc_core::types::{Actions, Layer, Profiles};
 use std::collections::HashMap;
 use std::env;
 use std::sync::Arc;
-use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::task::JoinHandle;
 use tracing::{error, info};
 use tracing_subscriber;
 
+mod images;
+mod integration_manager;
+mod pairing;
+mod profile_rules;
 mod profiles;
 mod rest_api;
+mod scheduler;
+mod state;
+mod sun;
+mod template;
 mod ws_api;
 
-const ACTION_SPLIT_CHARS: [char; 2] = [':', ':'];
-
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Config {
     integrations: Vec<IntegrationsConfigurationEnum>,
     profiles: Profiles,
+    // named action lists that buttons can run with macro::run
+    #[serde(default)]
+    macros: HashMap<String, Actions>,
+    // used to compute sunrise and sunset
+    location: Option<sun::Location>,
+    #[serde(default)]
+    schedules: Vec<scheduler::Schedule>,
+    #[serde(default)]
+    profile_rules: Vec<profile_rules::ProfileRule>,
 }
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("pair-hue") {
+        if let Err(err) = pairing::run_pair_hue_command(&args[1..]).await {
+            error!(error = ?err, "failed to pair with hue bridge");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let config_file =
         &env::var("STREAM_DECK_CONTROLLER_CONFIG").unwrap_or("./config.yaml".to_string());
     let config = read_config(config_file).expect("failed to read config file");
     info!(config_file, config = ?config, "parsed config file");
     let config_ref = Arc::new(config);
 
+    let state_file =
+        &env::var("STREAM_DECK_CONTROLLER_STATE").unwrap_or("./state.json".to_string());
+    let state = Arc::new(state::StateStore::load(state_file));
+
     let ws_clients = ws_api::Clients::default();
-    let image_cache = ws_api::ImageCache::default();
+    let image_cache = images::ImageCache::default();
     tokio::task::spawn(populat_image_cache(config_ref.clone(), image_cache.clone()));
 
-    let (integration_manager, integration_manager_tx) =
-        IntegrationManager::new(ws_clients.clone(), &config_ref)
-            .await
-            .unwrap_or_else(|err| {
+    let (integration_manager, integration_manager_tx, integration_manager_rx) =
+        IntegrationManager::new(ws_clients.clone(), &config_ref, state.clone()).unwrap_or_else(
+            |err| {
                 error!(error = ?err, "failed to create integration manager, cannot recover");
                 std::process::exit(1);
-            });
-    let manager_handle = start_integration_manager(integration_manager);
+            },
+        );
+    let integration_manager = Arc::new(integration_manager);
+    // integrations start in the background, see GET /v1/health for their status
+    integration_manager::start_integration_init(integration_manager.clone());
+    let manager_handle = integration_manager::start_integration_manager(
+        integration_manager.clone(),
+        integration_manager_rx,
+    );
+    integration_manager::start_integration_refresh(integration_manager.clone());
+
+    scheduler::start_schedules(
+        config_ref.clone(),
+        integration_manager_tx.clone(),
+        ws_clients.clone(),
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err, "failed to start schedules, cannot recover");
+        std::process::exit(1);
+    });
+
+    profile_rules::start_profile_rules(
+        config_ref.clone(),
+        integration_manager.clone(),
+        ws_clients.clone(),
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err, "failed to start profile rules, cannot recover");
+        std::process::exit(1);
+    });
 
     let api_service = rest_api::start_rest_api(
         config_ref,
         integration_manager_tx,
         ws_clients.clone(),
         image_cache.clone(),
+        state,
+        integration_manager,
     );
 
     tokio::task::spawn(ws_api::ping_ws_clients(ws_clients.clone()));
@@ -68,164 +124,22 @@ fn read_config(filepath: &str) -> Result<Config> {
     Ok(map)
 }
 
-async fn populat_image_cache(config_ref: Arc<Config>, image_cache: ws_api::ImageCache) {
+async fn populat_image_cache(config_ref: Arc<Config>, image_cache: images::ImageCache) {
     for profile in &config_ref.as_ref().profiles {
         for button in &profile.buttons {
             if let Some(states) = &button.states {
                 for state in states {
-                    if let Some(image) = &state.image {
-                        // eat this error, we will try again later when the client requests the image
-                        match ws_api::get_image(&image, &state, &image_cache).await {
-                            Ok(_) => (),
-                            Err(err) => error!(error=?err, "error populating image cache"),
-                        };
-                    }
-                }
-            }
-        }
-    }
-}
-
-struct IntegrationManager {
-    integrations: HashMap<String, IntegrationEnum>,
-    rx: Receiver<ExecuteActionReq>,
-    ws_clients: ws_api::Clients,
-}
-
-impl IntegrationManager {
-    async fn new(
-        ws_clients: ws_api::Clients,
-        config_ref: &Arc<Config>,
-    ) -> Result<(IntegrationManager, Sender<ExecuteActionReq>)> {
-        let (tx, rx) = mpsc::channel::<ExecuteActionReq>(32);
-
-        let mut manager = IntegrationManager {
-            integrations: HashMap::new(),
-            rx: rx,
-            ws_clients: ws_clients,
-        };
-
-        for integration in &config_ref.as_ref().integrations {
-            info!(
-                integration = integration.to_string(),
-                "setting up integration"
-            );
-            let i = integration.into_integration().await.map_err(|err| {
-                error!(?integration, error = ?err, "failed to create integration");
-                anyhow!(
-                    "failed to create integration {:?} with {:?}",
-                    integration.to_string(),
-                    err
-                )
-            })?;
-
-            manager.add_integration(i);
-        }
-
-        info!(
-            "enabled integration names: {:?}",
-            manager.integrations.keys()
-        );
-
-        return Ok((manager, tx));
-    }
-
-    fn add_integration(&mut self, integration: IntegrationEnum) {
-        self.integrations
-            .insert(integration.name().to_string(), integration);
-    }
-
-    async fn execute_actions(
-        &self,
-        requestor_uuid: Option<uuid::Uuid>,
-        actions: Actions,
-    ) -> Result<()> {
-        for action in actions {
-            let requestor_uuid = requestor_uuid
-                .ok_or_else(|| anyhow!("recieved excute actions request for unknown requestor"))?;
-
-            let split_index = action.action.find(ACTION_SPLIT_CHARS);
-            let (integration_name, action_name) = match split_index {
-                Some(i) => (
-                    &action.action[..i],
-                    &action.action[i + ACTION_SPLIT_CHARS.len()..],
-                ),
-                None => {
-                    return Err(anyhow!(
-                        "action {} was invalid, must contain separator.",
-                        action.action
-                    ))
-                }
-            };
-
-            if integration_name == "profile" {
-                if action_name != "set" {
-                    return Err(anyhow!(
-                        "unknown action for profile integration {}",
-                        action_name
-                    ));
-                }
-
-                let profile_value = &action
-                    .options
-                    .get("profile")
-                    .expect("invalid profile selection");
-
-                let r = match profile_value {
-                    serde_json::Value::String(profile_name) => {
-                        let mut clients = self.ws_clients.write().await;
-                        let client = clients.get_mut(&requestor_uuid).ok_or_else(|| {
-                            anyhow!("unable to get websocket client for {:?}", requestor_uuid)
-                        })?;
-                        client.profile = profile_name.to_string();
-                        Ok(())
+                    // bound values are only known once the button is rendered for a client
+                    if state.ui.layers.iter().flatten().any(Layer::is_bound) {
+                        continue;
                     }
-                    _ => Ok(()),
-                };
-
-                return r;
-            }
-
-            let mut options = action.options.clone();
-            options["action"] = serde_json::Value::String(action_name.to_string());
-            let integration_option = self.integrations.get(integration_name);
-
-            match integration_option {
-                Some(integration) => {
-                    integration
-                        .execute_action(action_name.to_string(), options)
-                        .await?;
+                    // eat this error, we will try again later when the client requests the image
+                    match images::render_button(&state.ui, None, &image_cache).await {
+                        Ok(_) => (),
+                        Err(err) => error!(error=?err, "error populating image cache"),
+                    };
                 }
-                None => return Err(anyhow!("unknown integration {}", integration_name)),
             }
         }
-
-        Ok(())
     }
 }
-
-fn start_integration_manager(mut integration_manager: IntegrationManager) -> JoinHandle<()> {
-    return tokio::spawn(async move {
-        // Start receiving messages
-        while let Some(execute_actions_req) = integration_manager.rx.recv().await {
-            let response = match integration_manager
-                .execute_actions(
-                    execute_actions_req.requestor_uuid,
-                    execute_actions_req.actions,
-                )
-                .await
-            {
-                Ok(_) => "success".to_string(),
-                Err(e) => {
-                    let msg = format!("error executing request: {}", e);
-                    info!("{}", msg);
-                    msg
-                }
-            };
-            // okay to eat this error, since that means the reciever is closed
-            let result = execute_actions_req.tx.send(response.to_string());
-        }
-
-        return ();
-    });
-}
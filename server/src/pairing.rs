@@ -0,0 +1,69 @@
+use anyhow::{anyhow, Result};
+use integrations::{hue, secrets};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+// how long to wait for the link button to be pressed
+const DEFAULT_PAIR_TIMEOUT_SEC: u64 = 60;
+const DEFAULT_HUE_SECRET: &str = "hue";
+
+// PairHueRequest selects the bridge to pair with, like the hue integration config, and the secret to
+// store the application key in. Reference the secret with auth_secret in the hue integration config.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct PairHueRequest {
+    pub secret: Option<String>,
+    pub bridge_id: Option<String>,
+    pub address: Option<Ipv4Addr>,
+    pub timeout_sec: Option<u64>,
+}
+
+// the application key is only written to the secrets file, never returned
+#[derive(Debug, serde::Serialize)]
+pub struct PairHueResponse {
+    pub bridge_id: String,
+    pub address: Ipv4Addr,
+    pub secret: String,
+}
+
+pub async fn pair_hue(request: PairHueRequest) -> Result<PairHueResponse> {
+    let secret = request
+        .secret
+        .unwrap_or_else(|| DEFAULT_HUE_SECRET.to_string());
+    let timeout = Duration::from_secs(request.timeout_sec.unwrap_or(DEFAULT_PAIR_TIMEOUT_SEC));
+
+    let paired = hue::pair(request.bridge_id.as_deref(), request.address, timeout).await?;
+    secrets::set(&secret, &paired.application_key)?;
+
+    Ok(PairHueResponse {
+        bridge_id: paired.bridge_id,
+        address: paired.address,
+        secret,
+    })
+}
+
+// run_pair_hue_command handles `server pair-hue [--secret NAME] [--bridge-id ID] [--address IP]
+// [--timeout SECONDS]`
+pub async fn run_pair_hue_command(args: &[String]) -> Result<()> {
+    let mut request = PairHueRequest::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| anyhow!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--secret" => request.secret = Some(value.to_string()),
+            "--bridge-id" => request.bridge_id = Some(value.to_string()),
+            "--address" => request.address = Some(value.parse()?),
+            "--timeout" => request.timeout_sec = Some(value.parse()?),
+            _ => return Err(anyhow!("unknown flag {}", flag)),
+        }
+    }
+
+    println!("press the link button on the hue bridge");
+    let paired = pair_hue(request).await?;
+    println!(
+        "paired with hue bridge {} at {}, set auth_secret: {} and bridge_id: {} in the hue integration config",
+        paired.bridge_id, paired.address, paired.secret, paired.bridge_id
+    );
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use crate::integration_manager::IntegrationManager;
+use crate::profiles;
+use crate::ws_api;
+use crate::Config;
+use anyhow::{anyhow, Result};
+use sdc_core::types::Condition;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+// conditions can depend on integration state, so they are polled rather than scheduled. Integrations
+// that push state changes trigger an evaluation right away.
+const RULE_POLL_INTERVAL_SEC: u64 = 10;
+
+// ProfileRule switches clients to profile while the condition holds, eg a morning profile between
+// 07:00 and 09:00, or a remote profile while the tv is playing
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ProfileRule {
+    pub name: Option<String>,
+    pub when: Condition,
+    pub profile: String,
+    // profile to switch back to once the condition stops holding
+    pub otherwise: Option<String>,
+    // names of the clients to switch, all clients when empty
+    #[serde(default)]
+    pub clients: Vec<String>,
+}
+
+impl ProfileRule {
+    fn display_name(&self, index: usize) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("profile rule {}", index))
+    }
+}
+
+// RuleState tracks the last result of a rule so profiles are only switched when it changes. Clients
+// are switched once per change, so a manual profile change sticks until the rule changes again.
+#[derive(Default)]
+struct RuleState {
+    matched: Option<bool>,
+    switched: HashSet<uuid::Uuid>,
+}
+
+pub fn start_profile_rules(
+    config: Arc<Config>,
+    integration_manager: Arc<IntegrationManager>,
+    clients: ws_api::Clients,
+) -> Result<()> {
+    for (index, rule) in config.profile_rules.iter().enumerate() {
+        let name = rule.display_name(index);
+        for profile in std::iter::once(&rule.profile).chain(rule.otherwise.iter()) {
+            profiles::get_profile_by_name(&config.profiles, profile.to_string())
+                .ok_or_else(|| anyhow!("{} uses unknown profile {}", name, profile))?;
+        }
+    }
+
+    if !config.profile_rules.is_empty() {
+        tokio::spawn(run_profile_rules(config, integration_manager, clients));
+    }
+    Ok(())
+}
+
+async fn run_profile_rules(
+    config: Arc<Config>,
+    integration_manager: Arc<IntegrationManager>,
+    clients: ws_api::Clients,
+) {
+    let mut states: Vec<RuleState> = config
+        .profile_rules
+        .iter()
+        .map(|_| RuleState::default())
+        .collect();
+    let mut state_changes = integration_manager.subscribe_state_changes();
+
+    loop {
+        for (index, (rule, state)) in config.profile_rules.iter().zip(&mut states).enumerate() {
+            let name = rule.display_name(index);
+            let matched = match integration_manager
+                .evaluate_condition(&rule.when, None)
+                .await
+            {
+                Ok(matched) => matched,
+                Err(err) => {
+                    // keep the current profiles, the next poll will try again
+                    error!(error=?err, rule = name, "failed to evaluate profile rule");
+                    continue;
+                }
+            };
+
+            if state.matched != Some(matched) {
+                info!(rule = name, matched, "profile rule changed");
+                state.matched = Some(matched);
+                state.switched.clear();
+            }
+
+            let profile = match (matched, &rule.otherwise) {
+                (true, _) => &rule.profile,
+                (false, Some(otherwise)) => otherwise,
+                (false, None) => continue,
+            };
+            // also picks up clients that connected since the rule changed
+            let switched = ws_api::switch_profile(&clients, profile, |client| {
+                client.matches(&rule.clients) && !state.switched.contains(&client.uuid)
+            })
+            .await;
+            state.switched.extend(switched);
+        }
+
+        tokio::select! {
+            _ = sleep(std::time::Duration::from_secs(RULE_POLL_INTERVAL_SEC)) => (),
+            _ = state_changes.changed() => (),
+        }
+    }
+}
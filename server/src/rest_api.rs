@@ -1,25 +1,45 @@
+use crate::images;
+use crate::integration_manager::{IntegrationHealth, IntegrationManager, MAX_MACRO_DEPTH};
+use crate::pairing;
 use crate::profiles;
+use crate::state;
 use crate::ws_api;
 use crate::Config;
 use anyhow::{anyhow, Result};
-use sdc_core::types::{Actions, ExecuteActionReq, ProfileButtonPressed, Profiles};
+use sdc_core::types::{
+    Action, Actions, ExecuteActionReq, ProfileButton, ProfileButtonPressed, Profiles,
+};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc::{self, Sender};
 use tokio::sync::oneshot;
 use tokio::time;
 use tracing::info;
-use warp::{http, Filter};
+use warp::{http, Filter, Reply};
+
+// how long a request waits for its actions on top of the delays they contain
+const ACTION_TIMEOUT_SEC: u64 = 5;
 
 pub async fn start_rest_api(
     config_ref: Arc<Config>,
     integration_manager_tx: Sender<ExecuteActionReq>,
     ws_clients: ws_api::Clients,
-    image_cache: ws_api::ImageCache,
+    image_cache: images::ImageCache,
+    state: state::State,
+    integration_manager: Arc<IntegrationManager>,
 ) {
+    let renderer = ws_api::Renderer {
+        config: config_ref.clone(),
+        image_cache,
+        state: state.clone(),
+        integration_manager: integration_manager.clone(),
+    };
     let event_processor = warp::any().map(move || integration_manager_tx.clone());
     let with_config = warp::any().map(move || config_ref.clone());
     let with_ws_clients = warp::any().map(move || ws_clients.clone());
-    let with_image_cache = warp::any().map(move || image_cache.clone());
+    let with_renderer = warp::any().map(move || renderer.clone());
+    let with_state = warp::any().map(move || state.clone());
+    let with_integration_manager = warp::any().map(move || integration_manager.clone());
     let with_none = warp::any().map(move || None);
 
     let log = warp::log("example::api");
@@ -29,23 +49,14 @@ pub async fn start_rest_api(
         // The `ws()` filter will prepare Websocket handshake...
         .and(warp::ws())
         .and(event_processor.clone())
-        .and(with_config.clone())
         .and(with_ws_clients)
-        .and(with_image_cache)
-        .map(
-            |ws: warp::ws::Ws, event_processor, config_ref, clients, image_cache| {
-                // This will call our function if the handshake succeeds.
-                ws.on_upgrade(move |socket| {
-                    ws_api::ws_client_connected(
-                        socket,
-                        event_processor,
-                        config_ref,
-                        clients,
-                        image_cache,
-                    )
-                })
-            },
-        );
+        .and(with_renderer)
+        .map(|ws: warp::ws::Ws, event_processor, clients, renderer| {
+            // This will call our function if the handshake succeeds.
+            ws.on_upgrade(move |socket| {
+                ws_api::ws_client_connected(socket, event_processor, clients, renderer)
+            })
+        });
 
     // POST /v1/actions/execute
     let execute_action_endpoint = warp::post()
@@ -53,6 +64,7 @@ pub async fn start_rest_api(
         .and(warp::path::end())
         .and(warp::body::json())
         .and(event_processor.clone())
+        .and(with_config.clone())
         .and(with_none)
         .and_then(handle_execute_action);
 
@@ -63,13 +75,50 @@ pub async fn start_rest_api(
         .and(warp::body::json())
         .and(event_processor.clone())
         .and(with_config)
+        .and(with_state)
         .and(with_none)
         .and_then(handle_button_pressed_action);
 
+    // GET /v1/integrations
+    let list_integrations_endpoint = warp::get()
+        .and(warp::path::end())
+        .and(with_integration_manager.clone())
+        .and_then(handle_list_integrations);
+
+    // GET /v1/integrations/{name}/state?{query}
+    let integration_state_endpoint = warp::get()
+        .and(warp::path!(String / "state"))
+        .and(warp::query::<serde_json::Map<String, serde_json::Value>>())
+        .and(with_integration_manager.clone())
+        .and_then(handle_integration_state);
+
+    // GET /v1/health
+    let health_endpoint = warp::get()
+        .and(warp::path("health"))
+        .and(warp::path::end())
+        .and(with_integration_manager)
+        .and_then(handle_health);
+
+    // POST /v1/hue/pair, waits for the link button so it can take up to the requested timeout
+    let hue_pair_endpoint = warp::post()
+        .and(warp::path!("hue" / "pair"))
+        .and(warp::body::json())
+        .and_then(handle_hue_pair);
+
     let actions_endpoint = warp::path("actions").and(execute_action_endpoint);
     let profiles_endpoint = warp::path("profiles").and(execute_button_press_endpoint);
 
-    let v1_endpoint = warp::path("v1").and(ws_endpoint.or(actions_endpoint).or(profiles_endpoint));
+    let integrations_endpoint =
+        warp::path("integrations").and(list_integrations_endpoint.or(integration_state_endpoint));
+
+    let v1_endpoint = warp::path("v1").and(
+        ws_endpoint
+            .or(actions_endpoint)
+            .or(profiles_endpoint)
+            .or(integrations_endpoint)
+            .or(health_endpoint)
+            .or(hue_pair_endpoint),
+    );
 
     // GET / -> index html
     let index_endpoint = warp::path::end().map(|| warp::reply::reply());
@@ -83,9 +132,10 @@ pub async fn start_rest_api(
 async fn handle_execute_action(
     actions: Actions,
     event_processor: Sender<ExecuteActionReq>,
+    config: Arc<Config>,
     requestor_uuid: Option<uuid::Uuid>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    match execute_action_request(actions.to_owned(), event_processor, requestor_uuid).await {
+    match execute_action_request(actions, event_processor, &config, requestor_uuid).await {
         Ok(r) => Ok(warp::reply::with_status(r, http::StatusCode::OK)),
         Err(e) => Ok(warp::reply::with_status(
             e.to_string(),
@@ -94,25 +144,75 @@ async fn handle_execute_action(
     }
 }
 
-pub async fn handle_button_pressed_action(
+async fn handle_list_integrations(
+    integration_manager: Arc<IntegrationManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&integration_manager.describe().await))
+}
+
+// the server keeps serving while integrations are down, so degraded is still a 200
+async fn handle_health(
+    integration_manager: Arc<IntegrationManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let integrations = integration_manager.health().await;
+    let status = if integrations
+        .values()
+        .all(|health| matches!(health, IntegrationHealth::Healthy))
+    {
+        "ok"
+    } else {
+        "degraded"
+    };
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": status,
+        "integrations": integrations,
+    })))
+}
+
+async fn handle_hue_pair(
+    request: pairing::PairHueRequest,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match pairing::pair_hue(request).await {
+        Ok(paired) => Ok(warp::reply::json(&paired).into_response()),
+        Err(e) => Ok(
+            warp::reply::with_status(e.to_string(), http::StatusCode::BAD_REQUEST).into_response(),
+        ),
+    }
+}
+
+async fn handle_integration_state(
+    integration_name: String,
+    query: serde_json::Map<String, serde_json::Value>,
+    integration_manager: Arc<IntegrationManager>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match integration_manager
+        .get_state(&integration_name, serde_json::Value::Object(query))
+        .await
+    {
+        Ok(state) => Ok(warp::reply::json(&state).into_response()),
+        Err(e) => Ok(
+            warp::reply::with_status(e.to_string(), http::StatusCode::BAD_REQUEST).into_response(),
+        ),
+    }
+}
+
+async fn handle_button_pressed_action(
     profile_button_pressed: ProfileButtonPressed,
     event_processor: mpsc::Sender<ExecuteActionReq>,
     config: Arc<Config>,
+    state: state::State,
     requestor_uuid: Option<uuid::Uuid>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    info!("{:?}", profile_button_pressed);
-
-    let actions = match get_actions_for_button_press(&config.profiles, profile_button_pressed) {
-        Ok(actions) => actions,
-        Err(e) => {
-            return Ok(warp::reply::with_status(
-                e.to_string(),
-                http::StatusCode::BAD_REQUEST,
-            ))
-        }
-    };
-
-    match execute_action_request(actions.to_owned(), event_processor, requestor_uuid).await {
+    match button_pressed(
+        profile_button_pressed,
+        event_processor,
+        config,
+        state,
+        requestor_uuid,
+    )
+    .await
+    {
         Ok(r) => Ok(warp::reply::with_status(r, http::StatusCode::OK)),
         Err(e) => Ok(warp::reply::with_status(
             e.to_string(),
@@ -121,13 +221,52 @@ pub async fn handle_button_pressed_action(
     }
 }
 
-async fn execute_action_request(
+pub async fn button_pressed(
+    profile_button_pressed: ProfileButtonPressed,
+    event_processor: mpsc::Sender<ExecuteActionReq>,
+    config: Arc<Config>,
+    state: state::State,
+    requestor_uuid: Option<uuid::Uuid>,
+) -> Result<String> {
+    info!("{:?}", profile_button_pressed);
+
+    let (profile_name, button) =
+        get_button_for_button_press(&config.profiles, &profile_button_pressed)?;
+
+    let mut actions = Actions::new();
+    let mut ran_state = None;
+    if button.is_cycling() {
+        let states = button.states.as_deref().unwrap_or_default();
+        let index = state
+            .advance_button_state(profile_name, profile_button_pressed.button, states.len())
+            .await?;
+        actions.extend(states[index].actions.iter().cloned());
+        ran_state = Some((index, states.len()));
+    }
+    actions.extend(button.actions.iter().cloned());
+
+    let result = execute_action_request(actions, event_processor, &config, requestor_uuid).await;
+
+    // a failed press goes back to the state it ran, so it can be retried
+    if let (Err(_), Some((index, len))) = (&result, ran_state) {
+        state
+            .revert_button_state(profile_name, profile_button_pressed.button, index, len)
+            .await?;
+    }
+    result
+}
+
+pub async fn execute_action_request(
     actions: Actions,
     event_processor: mpsc::Sender<ExecuteActionReq>,
+    config: &Config,
     requestor_uuid: Option<uuid::Uuid>,
 ) -> Result<String> {
     info!("{:?}", actions);
-    let (resp_tx, resp_rx) = oneshot::channel::<String>();
+    // sequences with delays take as long as their delays, so only the time spent on top counts
+    let timeout =
+        time::Duration::from_secs(ACTION_TIMEOUT_SEC) + actions_delay(&actions, &config.macros, 0);
+    let (resp_tx, resp_rx) = oneshot::channel::<Result<()>>();
 
     let execute_action_req = ExecuteActionReq {
         actions: actions,
@@ -137,10 +276,10 @@ async fn execute_action_request(
 
     event_processor.send(execute_action_req).await?;
 
-    // Wrap the future with a `Timeout` set to expire in 5 seconds.
-    match time::timeout(time::Duration::from_secs(5), resp_rx).await {
+    match time::timeout(timeout, resp_rx).await {
         Ok(resp) => match resp {
-            Ok(msg) => return Ok(msg),
+            Ok(Ok(_)) => Ok("success".to_string()),
+            Ok(Err(e)) => Err(anyhow!("error executing request: {}", e)),
             Err(e) => Err(anyhow::Error::new(e).context("error executing actions for request.")),
         },
         Err(e) => Err(anyhow::Error::new(e).context(
@@ -149,12 +288,56 @@ async fn execute_action_request(
     }
 }
 
-fn get_actions_for_button_press(
-    profiles: &Profiles,
-    profile_button_pressed: ProfileButtonPressed,
-) -> Result<&Actions> {
+// actions_delay is how long the delays in actions add up to when they run, parallel actions only
+// wait for their longest branch
+fn actions_delay(
+    actions: &Actions,
+    macros: &HashMap<String, Actions>,
+    macro_depth: usize,
+) -> time::Duration {
+    actions
+        .iter()
+        .map(|action| action_delay(action, macros, macro_depth))
+        .sum()
+}
+
+fn action_delay(
+    action: &Action,
+    macros: &HashMap<String, Actions>,
+    macro_depth: usize,
+) -> time::Duration {
+    match action {
+        Action::Delay(delay) => time::Duration::from_millis(delay.delay_ms),
+        Action::Parallel(parallel) => parallel
+            .parallel
+            .iter()
+            .map(|action| action_delay(action, macros, macro_depth))
+            .max()
+            .unwrap_or_default(),
+        Action::Conditional(conditional) => actions_delay(&conditional.then, macros, macro_depth)
+            .max(actions_delay(&conditional.otherwise, macros, macro_depth)),
+        Action::Integration(integration) if integration.action == "macro::run" => match integration
+            .options
+            .get("name")
+            .and_then(|name| name.as_str())
+            .and_then(|name| macros.get(name))
+        {
+            Some(actions) if macro_depth < MAX_MACRO_DEPTH => {
+                actions_delay(actions, macros, macro_depth + 1)
+            }
+            _ => time::Duration::ZERO,
+        },
+        Action::Integration(_) => time::Duration::ZERO,
+    }
+}
+
+fn get_button_for_button_press<'a>(
+    profiles: &'a Profiles,
+    profile_button_pressed: &ProfileButtonPressed,
+) -> Result<(&'a str, &'a ProfileButton)> {
     let profile = profile_button_pressed
         .profile
+        .as_ref()
         .ok_or_else(|| anyhow!("button press was not associated with any profile"))?;
     let profile = match profiles::get_profile_by_name(profiles, profile.clone()) {
         Some(profile) => profile,
@@ -171,5 +354,50 @@ fn get_actions_for_button_press(
         }
     };
 
-    return Ok(&button.actions);
+    Ok((&profile.name, button))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(value: serde_json::Value) -> Actions {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn actions_delay_covers_sequences() {
+        let macros = HashMap::from([(
+            "fade".to_string(),
+            actions(serde_json::json!([{"delay_ms": 1000}, {"action": "hue::toggle"}])),
+        )]);
+        let sequence = actions(serde_json::json!([
+            {"action": "tv::power_on"},
+            {"delay_ms": 8000},
+            {"parallel": [{"delay_ms": 200}, {"delay_ms": 500}]},
+            {"if": {"time": {"after": "sunset"}}, "then": [{"delay_ms": 300}]},
+            {"action": "macro::run", "name": "fade"},
+        ]));
+
+        assert_eq!(
+            actions_delay(&sequence, &macros, 0),
+            time::Duration::from_millis(8000 + 500 + 300 + 1000)
+        );
+    }
+
+    #[test]
+    fn actions_delay_stops_at_recursive_macros() {
+        let macros = HashMap::from([(
+            "again".to_string(),
+            actions(
+                serde_json::json!([{"delay_ms": 10}, {"action": "macro::run", "name": "again"}]),
+            ),
+        )]);
+        let sequence = actions(serde_json::json!([{"action": "macro::run", "name": "again"}]));
+
+        assert_eq!(
+            actions_delay(&sequence, &macros, 0),
+            time::Duration::from_millis(10 * MAX_MACRO_DEPTH as u64)
+        );
+    }
 }
@@ -0,0 +1,233 @@
+use crate::profiles;
+use crate::rest_api;
+use crate::sun::{Location, TimeOfDay};
+use crate::ws_api;
+use crate::Config;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use sdc_core::types::{Actions, ExecuteActionReq};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+// how far ahead to look for the next sunrise or sunset, covers polar night
+const MAX_SUN_SEARCH_DAYS: i64 = 366;
+
+// Schedule runs actions and/or switches profiles without a button press
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Schedule {
+    pub name: Option<String>,
+    // cron expression, seconds are optional, eg "0 22 * * *"
+    pub cron: Option<String>,
+    // once a day at HH:MM or sunrise/sunset with an optional offset in minutes, eg "sunset-30"
+    pub at: Option<String>,
+    #[serde(default)]
+    pub actions: Actions,
+    // switch clients to this profile
+    pub profile: Option<String>,
+    // names of the clients to switch, all clients when empty
+    #[serde(default)]
+    pub clients: Vec<String>,
+}
+
+enum Trigger {
+    Cron(Box<cron::Schedule>),
+    At(TimeOfDay),
+}
+
+impl Trigger {
+    // next returns the first time the trigger fires after after
+    fn next(
+        &self,
+        location: Option<&Location>,
+        after: DateTime<Local>,
+    ) -> Result<Option<DateTime<Local>>> {
+        match self {
+            Trigger::Cron(schedule) => Ok(schedule.after(&after).next()),
+            Trigger::At(time) => {
+                for day in 0..=MAX_SUN_SEARCH_DAYS {
+                    let date = after.date_naive() + chrono::Duration::days(day);
+                    match time.on(location, date)? {
+                        Some(next) if next > after => return Ok(Some(next)),
+                        _ => (),
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Schedule {
+    fn display_name(&self, index: usize) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("schedule {}", index))
+    }
+
+    fn trigger(&self) -> Result<Trigger> {
+        match (&self.cron, &self.at) {
+            (Some(expression), None) => {
+                let expression = normalize_cron(expression);
+                let schedule = cron::Schedule::from_str(&expression)
+                    .map_err(|err| anyhow!("invalid cron expression {:?}: {}", expression, err))?;
+                Ok(Trigger::Cron(Box::new(schedule)))
+            }
+            (None, Some(at)) => Ok(Trigger::At(at.parse()?)),
+            _ => Err(anyhow!("exactly one of cron or at must be set")),
+        }
+    }
+}
+
+// the cron crate requires seconds, default them so standard 5 field expressions work
+fn normalize_cron(expression: &str) -> String {
+    if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    }
+}
+
+// start_schedules validates every schedule and starts a task for each one
+pub fn start_schedules(
+    config: Arc<Config>,
+    event_processor: Sender<ExecuteActionReq>,
+    clients: ws_api::Clients,
+) -> Result<()> {
+    for (index, schedule) in config.schedules.iter().enumerate() {
+        let name = schedule.display_name(index);
+        let trigger = schedule
+            .trigger()
+            .map_err(|err| anyhow!("{} is invalid: {}", name, err))?;
+
+        if let Some(profile) = &schedule.profile {
+            profiles::get_profile_by_name(&config.profiles, profile.to_string())
+                .ok_or_else(|| anyhow!("{} uses unknown profile {}", name, profile))?;
+        }
+        // fail on startup rather than when the schedule first fires
+        trigger
+            .next(config.location.as_ref(), Local::now())
+            .map_err(|err| anyhow!("{} is invalid: {}", name, err))?;
+
+        info!(schedule = name, "starting schedule");
+        tokio::spawn(run_schedule(
+            config.clone(),
+            index,
+            trigger,
+            event_processor.clone(),
+            clients.clone(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn run_schedule(
+    config: Arc<Config>,
+    index: usize,
+    trigger: Trigger,
+    event_processor: Sender<ExecuteActionReq>,
+    clients: ws_api::Clients,
+) {
+    let schedule = &config.schedules[index];
+    let name = schedule.display_name(index);
+    let mut after = Local::now();
+
+    loop {
+        let next = match trigger.next(config.location.as_ref(), after) {
+            Ok(Some(next)) => next,
+            Ok(None) => {
+                warn!(schedule = name, "schedule will never run again, stopping");
+                return;
+            }
+            Err(err) => {
+                error!(error=?err, schedule = name, "failed to get next run, stopping");
+                return;
+            }
+        };
+        info!(schedule = name, %next, "waiting for next run");
+        sleep((next - Local::now()).to_std().unwrap_or_default()).await;
+        // compute the following run from when this one was due, so clock drift can't run it twice
+        after = next;
+
+        if let Some(profile) = &schedule.profile {
+            ws_api::switch_profile(&clients, profile, |client| {
+                client.matches(&schedule.clients)
+            })
+            .await;
+        }
+
+        if !schedule.actions.is_empty() {
+            match rest_api::execute_action_request(
+                schedule.actions.clone(),
+                event_processor.clone(),
+                &config,
+                None,
+            )
+            .await
+            {
+                Ok(_) => info!(schedule = name, "ran scheduled actions"),
+                Err(err) => error!(error=?err, schedule = name, "scheduled actions failed"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Timelike};
+
+    #[test]
+    fn five_field_cron_gets_seconds() {
+        assert_eq!(normalize_cron("0 22 * * *"), "0 0 22 * * *");
+        assert_eq!(normalize_cron("30 0 22 * * *"), "30 0 22 * * *");
+    }
+
+    #[test]
+    fn cron_trigger_runs_at_the_expression() {
+        let schedule = Schedule {
+            name: None,
+            cron: Some("15 22 * * *".to_string()),
+            at: None,
+            actions: Actions::new(),
+            profile: None,
+            clients: Vec::new(),
+        };
+        let after = Local.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        let next = schedule
+            .trigger()
+            .unwrap()
+            .next(None, after)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((next.hour(), next.minute(), next.second()), (22, 15, 0));
+        assert_eq!(next.date_naive(), after.date_naive());
+    }
+
+    #[test]
+    fn at_trigger_moves_to_the_next_day_once_passed() {
+        let trigger = Trigger::At("07:30".parse().unwrap());
+        let after = Local.with_ymd_and_hms(2024, 6, 21, 8, 0, 0).unwrap();
+        let next = trigger.next(None, after).unwrap().unwrap();
+
+        assert_eq!((next.hour(), next.minute()), (7, 30));
+        assert_eq!(next.date_naive(), after.date_naive().succ_opt().unwrap());
+    }
+
+    #[test]
+    fn trigger_requires_cron_or_at() {
+        let schedule = Schedule {
+            name: None,
+            cron: None,
+            at: None,
+            actions: Actions::new(),
+            profile: None,
+            clients: Vec::new(),
+        };
+        assert!(schedule.trigger().is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+pub type State = Arc<StateStore>;
+
+// PersistedState is everything the server needs to remember across restarts
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct PersistedState {
+    // profile name -> button index -> current state index of cycling buttons
+    #[serde(default)]
+    button_states: HashMap<String, HashMap<usize, usize>>,
+    // variables set with var:: actions
+    #[serde(default)]
+    vars: serde_json::Map<String, serde_json::Value>,
+    // client name -> variables only visible to that client
+    #[serde(default)]
+    client_vars: HashMap<String, serde_json::Map<String, serde_json::Value>>,
+}
+
+// StateStore keeps the state in memory and writes it to a json file on every change
+pub struct StateStore {
+    path: PathBuf,
+    state: RwLock<PersistedState>,
+}
+
+impl StateStore {
+    // load never fails, a state file that can't be read is moved aside so the server can still start
+    pub fn load(path: &str) -> StateStore {
+        let path = PathBuf::from(path);
+        let state = if path.exists() {
+            Self::read(&path).unwrap_or_else(|err| {
+                let backup = path.with_extension("json.corrupt");
+                error!(error = ?err, ?path, ?backup, "failed to read state file, starting with empty state");
+                if let Err(err) = std::fs::rename(&path, &backup) {
+                    error!(error = ?err, ?path, "failed to back up state file");
+                }
+                PersistedState::default()
+            })
+        } else {
+            info!(?path, "no state file found, starting with empty state");
+            PersistedState::default()
+        };
+
+        StateStore {
+            path,
+            state: RwLock::new(state),
+        }
+    }
+
+    fn read(path: &PathBuf) -> Result<PersistedState> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub async fn button_state(&self, profile: &str, button: usize) -> usize {
+        self.state
+            .read()
+            .await
+            .button_states
+            .get(profile)
+            .and_then(|buttons| buttons.get(&button))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // advance_button_state moves a cycling button with len states to its next state and returns the
+    // state to run. Done under one lock so quick presses each run a different state.
+    pub async fn advance_button_state(
+        &self,
+        profile: &str,
+        button: usize,
+        len: usize,
+    ) -> Result<usize> {
+        let mut state = self.state.write().await;
+        let current = state
+            .button_states
+            .entry(profile.to_string())
+            .or_default()
+            .entry(button)
+            .or_default();
+        let index = *current % len;
+        *current = (index + 1) % len;
+        self.save(&state).await?;
+        Ok(index)
+    }
+
+    // revert_button_state undoes advance_button_state for a press that failed, unless another press
+    // has advanced the button since
+    pub async fn revert_button_state(
+        &self,
+        profile: &str,
+        button: usize,
+        index: usize,
+        len: usize,
+    ) -> Result<()> {
+        let mut state = self.state.write().await;
+        match state
+            .button_states
+            .get_mut(profile)
+            .and_then(|buttons| buttons.get_mut(&button))
+        {
+            Some(current) if *current == (index + 1) % len => *current = index,
+            _ => return Ok(()),
+        }
+        self.save(&state).await
+    }
+
+    // vars returns the global variables overlaid with the client's own variables
+    pub async fn vars(&self, client: Option<&str>) -> serde_json::Map<String, serde_json::Value> {
+        let state = self.state.read().await;
+        let mut vars = state.vars.clone();
+        if let Some(client_vars) = client.and_then(|client| state.client_vars.get(client)) {
+            vars.extend(client_vars.clone());
+        }
+        vars
+    }
+
+    // update_var replaces a global variable, or a client variable when client is set, with the result
+    // of update called with its current value
+    pub async fn update_var<F>(
+        &self,
+        client: Option<&str>,
+        name: &str,
+        update: F,
+    ) -> Result<serde_json::Value>
+    where
+        F: FnOnce(Option<&serde_json::Value>) -> Result<serde_json::Value>,
+    {
+        let mut state = self.state.write().await;
+        let vars = match client {
+            Some(client) => state.client_vars.entry(client.to_string()).or_default(),
+            None => &mut state.vars,
+        };
+        let value = update(vars.get(name))?;
+        vars.insert(name.to_string(), value.clone());
+
+        self.save(&state).await?;
+        Ok(value)
+    }
+
+    // called with the lock held so writes can't be reordered
+    async fn save(&self, state: &PersistedState) -> Result<()> {
+        tokio::fs::write(&self.path, serde_json::to_string_pretty(state)?).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sdc-state-{}-{}.json", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn corrupt_state_file_starts_empty() {
+        let path = temp_path("corrupt");
+        std::fs::write(&path, "{\"button_states\": {").unwrap();
+
+        let store = StateStore::load(path.to_str().unwrap());
+        assert!(store.state.try_read().unwrap().button_states.is_empty());
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_file(path.with_extension("json.corrupt"));
+    }
+
+    #[tokio::test]
+    async fn presses_advance_to_different_states() {
+        let path = temp_path("advance");
+        let store = StateStore::load(path.to_str().unwrap());
+
+        let (first, second) = tokio::join!(
+            store.advance_button_state("home", 0, 3),
+            store.advance_button_state("home", 0, 3)
+        );
+        let mut ran = vec![first.unwrap(), second.unwrap()];
+        ran.sort();
+        assert_eq!(ran, vec![0, 1]);
+        assert_eq!(store.button_state("home", 0).await, 2);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn revert_only_undoes_its_own_press() {
+        let path = temp_path("revert");
+        let store = StateStore::load(path.to_str().unwrap());
+
+        let index = store.advance_button_state("home", 0, 2).await.unwrap();
+        store
+            .revert_button_state("home", 0, index, 2)
+            .await
+            .unwrap();
+        assert_eq!(store.button_state("home", 0).await, 0);
+
+        let first = store.advance_button_state("home", 0, 3).await.unwrap();
+        store.advance_button_state("home", 0, 3).await.unwrap();
+        store
+            .revert_button_state("home", 0, first, 3)
+            .await
+            .unwrap();
+        assert_eq!(store.button_state("home", 0).await, 2);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
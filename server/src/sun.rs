@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+
+// sun position is computed locally from the configured location using the sunrise equation, which is
+// accurate to within a minute or two and avoids depending on an external api
+// https://en.wikipedia.org/wiki/Sunrise_equation
+
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+const SECONDS_PER_DAY: f64 = 86400.0;
+// apparent sunset is when the top of the sun disappears, which includes refraction
+const SUN_ALTITUDE_DEG: f64 = -0.833;
+const EARTH_TILT_DEG: f64 = 23.4397;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+impl std::str::FromStr for SunEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<SunEvent> {
+        match s {
+            "sunrise" => Ok(SunEvent::Sunrise),
+            "sunset" => Ok(SunEvent::Sunset),
+            _ => Err(anyhow!("unknown sun event {}", s)),
+        }
+    }
+}
+
+// TimeOfDay is either a fixed local time (HH:MM) or a sun event with an optional offset in minutes,
+// eg sunset-30
+#[derive(Debug, Clone, Copy)]
+pub enum TimeOfDay {
+    Fixed(NaiveTime),
+    Sun { event: SunEvent, offset_min: i64 },
+}
+
+impl std::str::FromStr for TimeOfDay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<TimeOfDay> {
+        let s = s.trim();
+        if let Ok(time) = NaiveTime::parse_from_str(s, "%H:%M") {
+            return Ok(TimeOfDay::Fixed(time));
+        }
+
+        let (event, offset_min) = match s.find(['+', '-']) {
+            Some(i) => (&s[..i], s[i..].trim_start_matches('+').parse()?),
+            None => (s, 0),
+        };
+        let event = event
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("invalid time {:?}, expected HH:MM, sunrise or sunset", s))?;
+
+        Ok(TimeOfDay::Sun { event, offset_min })
+    }
+}
+
+impl TimeOfDay {
+    // on returns when the time happens on date, None when the sun doesn't rise or set that day
+    pub fn on(
+        &self,
+        location: Option<&Location>,
+        date: NaiveDate,
+    ) -> Result<Option<DateTime<Local>>> {
+        match self {
+            TimeOfDay::Fixed(time) => {
+                Ok(Local.from_local_datetime(&date.and_time(*time)).earliest())
+            }
+            TimeOfDay::Sun { event, offset_min } => {
+                let location = location.ok_or_else(|| {
+                    anyhow!("sunrise and sunset times require a location in the config")
+                })?;
+                Ok(sun_event(location, date, *event)
+                    .map(|t| t.with_timezone(&Local) + chrono::Duration::minutes(*offset_min)))
+            }
+        }
+    }
+}
+
+// sun_event returns when the event happens on date, or None when the sun doesn't rise or set that day
+// (polar day or night)
+pub fn sun_event(location: &Location, date: NaiveDate, event: SunEvent) -> Option<DateTime<Utc>> {
+    let noon = date.and_hms_opt(12, 0, 0)?.and_utc().timestamp() as f64;
+    let days_since_j2000 = (noon / SECONDS_PER_DAY + UNIX_EPOCH_JULIAN_DAY - J2000).round();
+
+    let mean_solar_time = days_since_j2000 - location.longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let m = mean_anomaly.to_radians();
+    let center = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000 + mean_solar_time + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+    let declination = (ecliptic_longitude.sin() * EARTH_TILT_DEG.to_radians().sin()).asin();
+    let latitude = location.latitude.to_radians();
+    let cos_hour_angle = (SUN_ALTITUDE_DEG.to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+    let julian_day = match event {
+        SunEvent::Sunrise => transit - hour_angle,
+        SunEvent::Sunset => transit + hour_angle,
+    };
+
+    let timestamp = ((julian_day - UNIX_EPOCH_JULIAN_DAY) * SECONDS_PER_DAY).round() as i64;
+    Utc.timestamp_opt(timestamp, 0).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: Location = Location {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+
+    fn assert_near(actual: DateTime<Utc>, hour: u32, minute: u32) {
+        let expected = actual
+            .date_naive()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+            .and_utc();
+        let diff = (actual - expected).num_seconds().abs();
+        assert!(diff <= 120, "expected about {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn sun_event_in_london_at_midsummer() {
+        // published times for 2024-06-21 are 03:43 and 20:21 UTC
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        assert_near(sun_event(&LONDON, date, SunEvent::Sunrise).unwrap(), 3, 43);
+        assert_near(sun_event(&LONDON, date, SunEvent::Sunset).unwrap(), 20, 21);
+    }
+
+    #[test]
+    fn sun_event_during_polar_day() {
+        let tromso = Location {
+            latitude: 69.6492,
+            longitude: 18.9553,
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        assert!(sun_event(&tromso, date, SunEvent::Sunset).is_none());
+    }
+
+    #[test]
+    fn parse_fixed_time() {
+        match "22:15".parse::<TimeOfDay>().unwrap() {
+            TimeOfDay::Fixed(time) => assert_eq!(time, NaiveTime::from_hms_opt(22, 15, 0).unwrap()),
+            time => panic!("parsed as {:?}", time),
+        }
+    }
+
+    #[test]
+    fn parse_sun_offsets() {
+        for (s, expected_event, expected_offset) in [
+            ("sunset", SunEvent::Sunset, 0),
+            ("sunset-30", SunEvent::Sunset, -30),
+            ("sunrise+15", SunEvent::Sunrise, 15),
+        ] {
+            match s.parse::<TimeOfDay>() {
+                Ok(TimeOfDay::Sun { event, offset_min }) => {
+                    assert_eq!(
+                        (event, offset_min),
+                        (expected_event, expected_offset),
+                        "{}",
+                        s
+                    )
+                }
+                time => panic!("{} parsed as {:?}", s, time),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_invalid_times() {
+        for s in ["25:00", "noon", "sunset-abc", ""] {
+            assert!(s.parse::<TimeOfDay>().is_err(), "{}", s);
+        }
+    }
+}
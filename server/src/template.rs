@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+// interpolate replaces {{root.path}} placeholders in every string of value with the matching entry of
+// context, eg {{params.room}} or {{vars.room}}. A string that is only a placeholder is replaced by the
+// raw value so numbers and objects keep their type. Placeholders for roots missing from context are
+// left as is, so they can be filled in later, as is a {{ without a closing }}.
+pub fn interpolate(value: &Value, context: &Value) -> Result<Value> {
+    match value {
+        Value::String(s) => interpolate_str(s, context),
+        Value::Array(items) => Ok(Value::Array(
+            items
+                .iter()
+                .map(|item| interpolate(item, context))
+                .collect::<Result<_>>()?,
+        )),
+        Value::Object(map) => Ok(Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), interpolate(v, context)?)))
+                .collect::<Result<_>>()?,
+        )),
+        _ => Ok(value.clone()),
+    }
+}
+
+fn interpolate_str(s: &str, context: &Value) -> Result<Value> {
+    let trimmed = s.trim();
+    if trimmed.starts_with(OPEN) && trimmed.ends_with(CLOSE) && trimmed.matches(OPEN).count() == 1 {
+        return match lookup(&trimmed[OPEN.len()..trimmed.len() - CLOSE.len()], context)? {
+            Some(value) => Ok(value.clone()),
+            None => Ok(Value::String(s.to_string())),
+        };
+    }
+
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find(OPEN) {
+        let end = match rest[start..].find(CLOSE) {
+            Some(end) => end + start,
+            None => break,
+        };
+
+        result.push_str(&rest[..start]);
+        match lookup(&rest[start + OPEN.len()..end], context)? {
+            Some(Value::String(v)) => result.push_str(v),
+            Some(v) => result.push_str(&v.to_string()),
+            None => result.push_str(&rest[start..end + CLOSE.len()]),
+        }
+        rest = &rest[end + CLOSE.len()..];
+    }
+    result.push_str(rest);
+
+    Ok(Value::String(result))
+}
+
+fn lookup<'a>(placeholder: &str, context: &'a Value) -> Result<Option<&'a Value>> {
+    let path = placeholder.trim();
+    let root = path.split('.').next().unwrap_or_default();
+    if context.get(root).is_none() {
+        return Ok(None);
+    }
+
+    let pointer = format!("/{}", path.replace('.', "/"));
+    context
+        .pointer(&pointer)
+        .map(Some)
+        .ok_or_else(|| anyhow!("missing value for {{{{{}}}}}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn macro_params_are_substituted() {
+        let params = json!({"params": {"room": "Office", "brightness": 40}});
+        let action = json!({
+            "action": "hue::set_light",
+            "room": "{{params.room}}",
+            "name": "{{ params.room }} lamp",
+            "brightness": "{{params.brightness}}",
+        });
+
+        assert_eq!(
+            interpolate(&action, &params).unwrap(),
+            json!({
+                "action": "hue::set_light",
+                "room": "Office",
+                "name": "Office lamp",
+                "brightness": 40,
+            })
+        );
+    }
+
+    #[test]
+    fn params_inside_arrays_are_substituted() {
+        let params = json!({"params": {"room": "Office"}});
+        let actions = json!([{"parallel": [{"room": "{{params.room}}"}]}]);
+
+        assert_eq!(
+            interpolate(&actions, &params).unwrap(),
+            json!([{"parallel": [{"room": "Office"}]}])
+        );
+    }
+
+    #[test]
+    fn nested_paths() {
+        let context = json!({"vars": {"rooms": {"office": {"scene": "Focus"}}}});
+        assert_eq!(
+            interpolate(&json!("scene {{vars.rooms.office.scene}}"), &context).unwrap(),
+            json!("scene Focus")
+        );
+    }
+
+    #[test]
+    fn non_string_values() {
+        let context = json!({"vars": {"on": true, "level": 2.5, "rooms": ["office"]}});
+        assert_eq!(
+            interpolate(&json!("{{vars.on}}"), &context).unwrap(),
+            json!(true)
+        );
+        assert_eq!(
+            interpolate(&json!("{{vars.rooms}}"), &context).unwrap(),
+            json!(["office"])
+        );
+        assert_eq!(
+            interpolate(&json!("level {{vars.level}} of {{vars.rooms}}"), &context).unwrap(),
+            json!("level 2.5 of [\"office\"]")
+        );
+        assert_eq!(interpolate(&json!(3), &context).unwrap(), json!(3));
+    }
+
+    #[test]
+    fn unknown_roots_are_kept() {
+        let context = json!({"vars": {"room": "Office"}});
+        assert_eq!(
+            interpolate(&json!("{{params.room}} {{vars.room}}"), &context).unwrap(),
+            json!("{{params.room}} Office")
+        );
+        assert_eq!(
+            interpolate(&json!("{{params.room}}"), &context).unwrap(),
+            json!("{{params.room}}")
+        );
+    }
+
+    #[test]
+    fn literal_open_braces_are_kept() {
+        let context = json!({"vars": {"room": "Office"}});
+        assert_eq!(
+            interpolate(&json!("{\"a\": {{\"b\": 1}"), &context).unwrap(),
+            json!("{\"a\": {{\"b\": 1}")
+        );
+        assert_eq!(
+            interpolate(&json!("{{vars.room}} {{"), &context).unwrap(),
+            json!("Office {{")
+        );
+    }
+
+    #[test]
+    fn missing_param_is_an_error() {
+        let params = json!({"params": {}});
+        assert!(interpolate(&json!("{{params.room}}"), &params).is_err());
+    }
+}
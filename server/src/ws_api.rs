@@ -1,10 +1,14 @@
+use crate::images::{self, ImageCache};
+use crate::integration_manager::IntegrationManager;
 use crate::profiles;
+use crate::state;
 use crate::Config;
 use anyhow::{anyhow, Result};
 use futures_util::FutureExt;
 use futures_util::StreamExt;
-use image::{self, Pixel};
-use sdc_core::types::{ExecuteActionReq, ProfileButtonPressed, SetButtonUI, WsActions};
+use sdc_core::types::{
+    DeckLayout, ExecuteActionReq, Layer, Profile, ProfileButtonPressed, SetButtonUI, WsActions,
+};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc;
@@ -16,14 +20,36 @@ use tracing::{error, info};
 use warp::ws::{Message, WebSocket};
 
 const PING_INTERVAL_MIN: u64 = 15;
+const RESULT_FLASH_MS: u64 = 600;
 
 pub struct Client {
     pub uuid: uuid::Uuid,
     pub profile: String,
+    // reported by the client after connecting, needed to split profile backgrounds across keys
+    pub layout: Option<DeckLayout>,
+    // reported by the client, used to target it from the config
+    pub name: Option<String>,
+    // request a resync of the client's buttons, eg after changing its profile
+    pub profile_sync: Arc<UnboundedSender<()>>,
     pub sender: mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>,
 }
 pub type Clients = Arc<RwLock<HashMap<uuid::Uuid, Client>>>;
-pub type ImageCache = Arc<RwLock<HashMap<String, String>>>;
+
+// Renderer holds what is needed to render buttons for clients
+#[derive(Clone)]
+pub struct Renderer {
+    pub config: Arc<Config>,
+    pub image_cache: ImageCache,
+    pub state: state::State,
+    pub integration_manager: Arc<IntegrationManager>,
+}
+
+impl Client {
+    // matches checks if the client is one of names, an empty list matches every client
+    pub fn matches(&self, names: &[String]) -> bool {
+        names.is_empty() || self.name.as_ref().is_some_and(|name| names.contains(name))
+    }
+}
 
 pub async fn ping_ws_clients(clients: Clients) {
     loop {
@@ -44,9 +70,8 @@ async fn ping_all_ws_clients(clients: Clients) {
 pub async fn ws_client_connected(
     ws: WebSocket,
     event_processor: mpsc::Sender<ExecuteActionReq>,
-    config: Arc<Config>,
     clients: Clients,
-    image_cache: ImageCache,
+    renderer: Renderer,
 ) {
     let id = uuid::Uuid::new_v4();
 
@@ -60,26 +85,28 @@ pub async fn ws_client_connected(
         }
     }));
 
+    let (profile_sync_tx, profile_sync_rx) = mpsc::unbounded_channel::<()>();
+    let profile_sync_tx = Arc::new(profile_sync_tx);
+
     clients.write().await.insert(
         id,
         Client {
             uuid: id,
             profile: "default".to_string(),
+            layout: None,
+            name: None,
+            profile_sync: profile_sync_tx.clone(),
             sender: client_sender,
         },
     );
     info!("new websocket client: {}", id);
 
     // Split the socket into a sender and receive of messages.
-
-    let (profile_sync_tx, profile_sync_rx) = mpsc::unbounded_channel::<()>();
-    let profile_sync_tx = Arc::new(profile_sync_tx);
     tokio::spawn(profile_sync_task(
-        config.clone(),
+        renderer.clone(),
         id,
         clients.clone(),
         profile_sync_rx,
-        image_cache,
     ));
 
     match profile_sync_tx.send(()) {
@@ -93,7 +120,7 @@ pub async fn ws_client_connected(
             clients.clone(),
             profile_sync_tx.clone(),
             event_processor.clone(),
-            config.clone(),
+            renderer.clone(),
             result,
         )
         .await
@@ -112,7 +139,7 @@ async fn handle_msg(
     clients: Arc<RwLock<HashMap<uuid::Uuid, Client>>>,
     profile_sync_tx: Arc<UnboundedSender<()>>,
     event_processor: mpsc::Sender<ExecuteActionReq>,
-    config: Arc<Config>,
+    renderer: Renderer,
     result: Result<Message, warp::Error>,
 ) -> Result<()> {
     let msg = match result {
@@ -133,7 +160,38 @@ async fn handle_msg(
     }
 
     let msg_str = String::from_utf8(msg.into_bytes().to_vec())?;
-    let mut p: ProfileButtonPressed = serde_json::from_str(&msg_str)?;
+    // older clients send the button press without a message type, so fallback to parsing that
+    let mut p: ProfileButtonPressed = match serde_json::from_str::<WsActions>(&msg_str) {
+        Ok(WsActions::ClientInfo { layout, name }) => {
+            info!(?id, ?layout, ?name, "received client info");
+            // without a layout the client only gets the keys, not the background
+            let layout = match images::check_layout(&layout) {
+                Ok(()) => Some(layout),
+                Err(err) => {
+                    error!(error=?err, ?id, "ignoring invalid client layout");
+                    None
+                }
+            };
+            let mut locked = clients.write().await;
+            let client = locked
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("failed to find client"))?;
+            client.layout = layout;
+            client.name = name;
+            return Ok(profile_sync_tx.send(())?);
+        }
+        Ok(WsActions::ButtonPressed { profile, button }) => ProfileButtonPressed {
+            profile,
+            button: button.into(),
+        },
+        Ok(msg) => {
+            return Err(anyhow!(
+                "unexpected message type from client: {}",
+                msg.type_string()
+            ))
+        }
+        Err(_) => serde_json::from_str(&msg_str)?,
+    };
     if p.profile.is_none() {
         p.profile = Some(
             clients
@@ -147,20 +205,95 @@ async fn handle_msg(
     }
 
     info!("{:?}", &p);
-    // p.profile = p.profile.unwrap_or()
-    crate::rest_api::handle_button_pressed_action(
+    // presses run in their own task, so a button running a sequence with delays doesn't hold up the
+    // other keys of the deck
+    tokio::spawn(handle_button_press(
+        id,
         p,
-        event_processor.clone(),
-        config.clone(),
+        clients,
+        profile_sync_tx,
+        event_processor,
+        renderer,
+    ));
+    Ok(())
+}
+
+// handle_button_press runs the actions of a pressed button, shows their result on the key and
+// resyncs the client afterwards
+async fn handle_button_press(
+    id: uuid::Uuid,
+    p: ProfileButtonPressed,
+    clients: Clients,
+    profile_sync_tx: Arc<UnboundedSender<()>>,
+    event_processor: mpsc::Sender<ExecuteActionReq>,
+    renderer: Renderer,
+) {
+    let button = p.button;
+    let profile = p.profile.clone().unwrap_or_default();
+    let result = crate::rest_api::button_pressed(
+        p,
+        event_processor,
+        renderer.config.clone(),
+        renderer.state.clone(),
         Some(id),
     )
-    .await
-    .map_err(|err| anyhow!("button pressed handler rejected the request: {:?}", err))?;
+    .await;
+    if let Err(err) = &result {
+        error!(error=?err, uuid=?id, "button pressed handler rejected the request");
+    }
+
+    match send_action_result(&clients, id, button, &result).await {
+        Ok(_) => (),
+        Err(err) => error!(error=?err, uuid=?id, "failed to send action result"),
+    }
+
+    let flash_delay =
+        match send_result_flash(&renderer, &clients, id, &profile, button, result.is_ok()).await {
+            Ok(true) => std::time::Duration::from_millis(RESULT_FLASH_MS),
+            Ok(false) => std::time::Duration::ZERO,
+            Err(err) => {
+                error!(error=?err, uuid=?id, "failed to send result flash");
+                std::time::Duration::ZERO
+            }
+        };
 
     // todo: this is pretty silly, since every button press will trigger a full ui resyn, really
     // this should be smart and only resync if there are changes
+    // the resync also clears the result flash, so wait for it to be seen
+    sleep(flash_delay).await;
     info!("Sending profile");
-    Ok(profile_sync_tx.send(())?)
+    if let Err(err) = profile_sync_tx.send(()) {
+        error!(error=?err, uuid=?id, "failed to request profile sync");
+    }
+}
+
+// switch_profile moves every client matching filter to profile and resyncs their buttons, returning
+// the clients that were switched
+pub async fn switch_profile<F>(clients: &Clients, profile: &str, filter: F) -> Vec<uuid::Uuid>
+where
+    F: Fn(&Client) -> bool,
+{
+    let mut switched = Vec::new();
+    let mut locked = clients.write().await;
+    for client in locked.values_mut().filter(|client| filter(client)) {
+        info!(client=?client.uuid, name=?client.name, profile, "switching profile");
+        client.profile = profile.to_string();
+        if let Err(err) = client.profile_sync.send(()) {
+            error!(error=?err, client=?client.uuid, "failed to request profile sync");
+        }
+        switched.push(client.uuid);
+    }
+
+    switched
+}
+
+// resync_clients rerenders the buttons of every client, eg after a value that buttons show changed
+pub async fn resync_clients(clients: &Clients) {
+    for client in clients.read().await.values() {
+        if let Err(err) = client.profile_sync.send(()) {
+            error!(error=?err, client=?client.uuid, "failed to request profile sync");
+        }
+    }
 }
 
 async fn client_disconnected(clients: Clients, id: uuid::Uuid) {
@@ -171,14 +304,13 @@ async fn client_disconnected(clients: Clients, id: uuid::Uuid) {
 }
 
 async fn profile_sync_task(
-    config: Arc<Config>,
+    renderer: Renderer,
     id: uuid::Uuid,
     clients: Clients,
     mut profile_sync_rx: mpsc::UnboundedReceiver<()>,
-    image_cache: ImageCache,
 ) {
-    while let Some(_) = profile_sync_rx.recv().await {
-        match handle_profile_sync_request(&config, &clients, id, &image_cache).await {
+    while profile_sync_rx.recv().await.is_some() {
+        match handle_profile_sync_request(&renderer, &clients, id).await {
             Ok(_) => (),
             Err(err) => {
                 error!(error=?err, uuid=?id, "failed to sync profile")
@@ -188,46 +320,11 @@ async fn profile_sync_task(
 }
 
 async fn handle_profile_sync_request(
-    config: &Arc<Config>,
-    clients: &Arc<RwLock<HashMap<uuid::Uuid, Client>>>,
+    renderer: &Renderer,
+    clients: &Clients,
     id: uuid::Uuid,
-    image_cache: &Arc<RwLock<HashMap<String, String>>>,
 ) -> Result<()> {
-    let mut button_config = Vec::new();
-    let profile = profiles::get_profile_by_name(
-        &config.as_ref().profiles,
-        clients
-            .read()
-            .await
-            .get(&id)
-            .ok_or_else(|| anyhow!("failed to get client for id"))?
-            .profile
-            .to_string(),
-    )
-    .ok_or_else(|| anyhow!("failed to find profile for client"))?;
-
-    for button in &profile.buttons {
-        let button_state: &SetButtonUI = &button.states.as_ref().unwrap()[0];
-
-        let image = match &button_state.image {
-            Some(image) => {
-                get_image(image, button_state, image_cache)
-                    .await
-                    // log error, because its getting eaten
-                    .map_err(|err| {
-                        error!(error=?err, image, "failed to get image, skipping");
-                        err
-                    })
-                    .ok()
-            }
-            None => None,
-        };
-
-        button_config.push(SetButtonUI {
-            image: image,
-            color: button_state.color.clone(),
-        });
-    }
+    let (profile_name, button_config) = render_client_buttons(renderer, clients, id).await?;
     let msg = WsActions::SetButtons {
         buttons: button_config,
     };
@@ -239,7 +336,7 @@ async fn handle_profile_sync_request(
         }
     };
     let msg = Message::text(msg);
-    info!(client=?id, profile=?profile.name, "sending set button event");
+    info!(client=?id, profile=?profile_name, "sending set button event");
     match send_ws_message(&id, clients.clone(), msg).await {
         Ok(_) => (),
         Err(err) => error!(error =?err, client=?id, "failed to send button pressed message"),
@@ -247,100 +344,165 @@ async fn handle_profile_sync_request(
     Ok(())
 }
 
-async fn send_ws_message(
-    id: &uuid::Uuid,
-    clients: Clients,
-    message: Message,
-) -> Result<(), mpsc::error::SendError<Result<Message, warp::Error>>> {
-    let locked = clients.read().await;
-    match locked.get(&id) {
-        Some(c) => return c.sender.send(Ok(message)),
-        None => Ok(()),
-    }
+async fn send_action_result(
+    clients: &Clients,
+    id: uuid::Uuid,
+    button: usize,
+    result: &Result<String>,
+) -> Result<()> {
+    let msg = WsActions::ActionResult {
+        button: button.try_into()?,
+        success: result.is_ok(),
+        message: match result {
+            Ok(msg) => msg.to_string(),
+            Err(err) => err.to_string(),
+        },
+    };
+    let msg = Message::text(serde_json::to_string(&msg)?);
+    send_ws_message(&id, clients.clone(), msg).await?;
+    Ok(())
 }
 
-pub async fn get_image(
-    image: &String,
-    button_state: &SetButtonUI,
-    image_cache: &Arc<RwLock<HashMap<String, String>>>,
-) -> Result<String> {
-    let cache_key = format!(
-        "{}-{}",
-        image,
-        button_state.color.as_ref().unwrap_or(&"".to_string())
-    );
-
-    {
-        let cached_locked = image_cache.read().await;
-        let cached_image = cached_locked.get(&cache_key);
-        match cached_image {
-            Some(cached_image) => {
-                return Ok(cached_image.to_string());
-            }
-            None => (),
-        }
-    }
-    let mut loaded_image = fetch_image(image).await?;
-
-    // apply background if color is also set
-    // steal this from the streamdeck library, to avoid it as a dependency for the api
-    info!(image=?image, color=?button_state.color, "loading image");
-    if let Some(color) = &button_state.color {
-        let (r, g, b) = hex_color_components_from_str(&color)
-            .map_err(|err| anyhow!("unable to decoed hex color: {}", err))?;
-        let rgba = loaded_image.as_mut_rgba8().ok_or_else(|| {
-            anyhow!("unable to convert image to have transparent layer, is it a png?")
-        })?;
-
-        let mut r = image::Rgba([r, g, b, 0]);
-        for p in rgba.pixels_mut() {
-            r.0[3] = 255 - p.0[3];
-
-            p.blend(&r);
-        }
+// send_result_flash briefly overlays the outcome of a button press on the key, the profile sync
+// that follows restores the normal button. The flash is skipped when the actions switched the
+// client to another profile, since the key now belongs to a different button.
+async fn send_result_flash(
+    renderer: &Renderer,
+    clients: &Clients,
+    id: uuid::Uuid,
+    profile: &str,
+    button: usize,
+    success: bool,
+) -> Result<bool> {
+    let (current_profile, layout) = client_profile(clients, id).await?;
+    if current_profile != profile {
+        return Ok(false);
     }
 
-    let mut buffered_image = Vec::new();
-    loaded_image
-        .resize(100, 100, image::imageops::FilterType::Nearest)
-        .write_to(
-            &mut std::io::Cursor::new(&mut buffered_image),
-            image::ImageOutputFormat::Png,
-        )
-        .map_err(|err| anyhow!("unable to write image to buffer: {}", err))?;
+    let profile = profiles::get_profile_by_name(&renderer.config.profiles, current_profile)
+        .ok_or_else(|| anyhow!("failed to find profile for client"))?;
+    let tiles = profile_tiles(renderer, profile, layout.as_ref()).await;
+    let button_state =
+        render_profile_button(renderer, profile, button, tiles.get(button), id).await;
+
+    let msg = WsActions::FlashButton {
+        index: button.try_into()?,
+        button: SetButtonUI {
+            image: Some(images::render_result_flash(&button_state, success)?),
+            ..Default::default()
+        },
+    };
+    let msg = Message::text(serde_json::to_string(&msg)?);
+    send_ws_message(&id, clients.clone(), msg).await?;
+    Ok(true)
+}
 
-    let base64_encoded = base64::encode(&buffered_image);
-    image_cache
-        .write()
-        .await
-        .insert(cache_key, base64_encoded.to_string());
-    Ok(base64_encoded)
+async fn client_profile(clients: &Clients, id: uuid::Uuid) -> Result<(String, Option<DeckLayout>)> {
+    let locked = clients.read().await;
+    let client = locked
+        .get(&id)
+        .ok_or_else(|| anyhow!("failed to get client for id"))?;
+    Ok((client.profile.to_string(), client.layout))
 }
 
-async fn fetch_image(image: &String) -> Result<image::DynamicImage> {
-    if image.starts_with("http") {
-        return load_image_from_url(image).await;
+// render_client_buttons renders the buttons of the client's current profile into what is sent to
+// the client
+async fn render_client_buttons(
+    renderer: &Renderer,
+    clients: &Clients,
+    id: uuid::Uuid,
+) -> Result<(String, Vec<SetButtonUI>)> {
+    let (profile_name, layout) = client_profile(clients, id).await?;
+    let profile = profiles::get_profile_by_name(&renderer.config.profiles, profile_name)
+        .ok_or_else(|| anyhow!("failed to find profile for client"))?;
+    let tiles = profile_tiles(renderer, profile, layout.as_ref()).await;
+
+    // keys without a button still show their part of the background
+    let mut button_config = Vec::new();
+    for i in 0..profile.buttons.len().max(tiles.len()) {
+        button_config.push(render_profile_button(renderer, profile, i, tiles.get(i), id).await);
     }
 
-    let loaded_image = image::open(&image)?;
-    Ok(loaded_image)
+    Ok((profile.name.to_string(), button_config))
 }
 
-async fn load_image_from_url(image: &String) -> Result<image::DynamicImage> {
-    let img_bytes = reqwest::get(image).await?.bytes().await?;
-    let image = image::load_from_memory(&img_bytes)?;
-    Ok(image)
+async fn profile_tiles(
+    renderer: &Renderer,
+    profile: &Profile,
+    layout: Option<&DeckLayout>,
+) -> Vec<images::BackgroundTile> {
+    match (&profile.background, layout) {
+        (Some(background), Some(layout)) => {
+            images::background_tiles(background, layout, &renderer.image_cache)
+                .await
+                // log error, because its getting eaten
+                .map_err(|err| {
+                    error!(error=?err, background, "failed to split background, skipping");
+                    err
+                })
+                .unwrap_or_default()
+        }
+        _ => Vec::new(),
+    }
 }
 
-// steal this from the streamdeck library, to avoid it as a dependency for the api
-fn hex_color_components_from_str(s: &str) -> Result<(u8, u8, u8)> {
-    if s.len() != 6 && s.len() != 8 {
-        return Err(anyhow!("Expected colour in the hex form: RRGGBB"));
-    }
+// render_profile_button renders a single button of profile as it is sent to client id
+async fn render_profile_button(
+    renderer: &Renderer,
+    profile: &Profile,
+    i: usize,
+    tile: Option<&images::BackgroundTile>,
+    id: uuid::Uuid,
+) -> SetButtonUI {
+    let empty_state = SetButtonUI::default();
+    let button_state: &SetButtonUI = match profile.buttons.get(i).map(|b| &b.states) {
+        // cycling buttons show the state the next press will run
+        Some(Some(states)) if !states.is_empty() => {
+            &states[renderer.state.button_state(&profile.name, i).await % states.len()].ui
+        }
+        _ => &empty_state,
+    };
+    let resolved;
+    let button_state = match &button_state.layers {
+        Some(layers) if layers.iter().any(Layer::is_bound) => {
+            resolved = SetButtonUI {
+                layers: Some(
+                    renderer
+                        .integration_manager
+                        .resolve_layers(layers, Some(id))
+                        .await,
+                ),
+                ..button_state.clone()
+            };
+            &resolved
+        }
+        _ => button_state,
+    };
 
-    let r = u8::from_str_radix(&s[0..2], 16).map_err(|e| anyhow!("int parsing error: {}", e))?;
-    let g = u8::from_str_radix(&s[2..4], 16).map_err(|e| anyhow!("int parsing error: {}", e))?;
-    let b = u8::from_str_radix(&s[4..6], 16).map_err(|e| anyhow!("int parsing error: {}", e))?;
+    let image = images::render_button(button_state, tile, &renderer.image_cache)
+        .await
+        // log error, because its getting eaten
+        .map_err(|err| {
+            error!(error=?err, image=?button_state.image, "failed to get image, skipping");
+            err
+        })
+        .unwrap_or_default();
+
+    SetButtonUI {
+        image,
+        color: button_state.color.clone(),
+        layers: None,
+    }
+}
 
-    Ok((r, g, b))
+async fn send_ws_message(
+    id: &uuid::Uuid,
+    clients: Clients,
+    message: Message,
+) -> Result<(), mpsc::error::SendError<Result<Message, warp::Error>>> {
+    let locked = clients.read().await;
+    match locked.get(&id) {
+        Some(c) => return c.sender.send(Ok(message)),
+        None => Ok(()),
+    }
 }